@@ -0,0 +1,69 @@
+use clap::{Parser, Subcommand};
+
+/// XtracT: filters, splits and transforms XML files according to a TOML configuration.
+///
+/// The paths to the configuration files are taken from the command-line options or,
+/// if not given, from the environment variables CONFIG, MSG_CONFIG and LOG4RS
+/// (which may be set in an optional .env file).
+#[derive(Parser, Debug)]
+#[command(name = "xtract", version)]
+pub struct Cli {
+    /// Path to the TOML configuration file (overrides CONFIG)
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Path to the message configuration file (overrides MSG_CONFIG)
+    #[arg(long, global = true, value_name = "FILE")]
+    pub messages: Option<String>,
+
+    /// Path to the log4rs configuration file (overrides LOG4RS); without it, messages are logged to the console
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_config: Option<String>,
+
+    /// XML file to process instead of the file(s) in the original directory (it is not archived); "-" reads from stdin
+    #[arg(long, global = true, value_name = "FILE")]
+    pub input: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Filter, split and transform the original file(s), upload and archive the results (default)
+    Run,
+    /// Check the filter and split settings for consistency
+    Check,
+    /// Filter, split and transform the original file(s) without writing, uploading or archiving anything
+    DryRun,
+    /// Validate the configuration
+    Validate,
+    /// Trace the filter, grouping and transformation decisions for selected split elements without writing anything
+    Explain {
+        /// Index of a split element to trace (starting at 1, may be repeated)
+        #[arg(long, value_name = "N")]
+        index: Vec<usize>,
+
+        /// Trace the split elements containing the given value at the given path (element or attribute)
+        #[arg(long, value_name = "PATH=VALUE", value_parser = parse_key)]
+        key: Option<(String, String)>,
+    },
+    /// Watch the original directory and process every new file as soon as it is complete
+    Watch,
+    /// Run the golden-file test cases in a directory and compare their output with the expected output
+    Test {
+        /// Directory of a test case or directory containing test cases (one per subdirectory)
+        dir: String,
+
+        /// Replace the expected output by the actual output instead of comparing them
+        #[arg(long)]
+        update: bool,
+    },
+}
+
+fn parse_key(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once("=") {
+        Some((path, value)) if !path.is_empty() => Ok((path.to_string(), value.to_string())),
+        _ => Err(format!("expected PATH=VALUE, got {:?}", arg)),
+    }
+}
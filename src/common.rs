@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use quick_xml::events::{Event, BytesStart, BytesText, BytesEnd};
+
+use crate::config::*;
+use crate::output::Sink;
+use crate::error::{Error, Result};
+use crate::validation::parse_config;
+
+pub fn get_msg(msg_config: &HashMap<String, HashMap<String, String>>, msg_key: &str, lang: &String) -> String {
+    match msg_config
+    .get(msg_key)
+    .and_then(|map| map.get(lang)) {
+        Some(msg) => msg.clone().to_string(),
+        None => msg_key.to_string(),
+    }
+}
+
+pub fn superordinate(path1: &Vec<String>, path2: &Vec<&str>) -> bool {
+    let matching = path2.iter().zip(path1).filter(|&(a, b)| a == b);
+    matching.count() < path2.len()
+}
+
+pub fn embed<'a>(value: String, path: String) -> Vec<Event<'a>> {
+    let mut new_element_names: Vec<String> = Vec::new();
+    let mut new_elements: Vec<Event<'_>> = Vec::new();
+    let split_path= path.split("/");
+    for name in split_path {
+        new_element_names.push(name.to_string());
+    }
+    for element in &new_element_names {
+        let start_tag = BytesStart::new( element.clone());
+        new_elements.push(Event::Start(start_tag));
+    }
+    new_elements.push(Event::Text(BytesText::new(&value).into_owned()));
+    new_element_names.reverse();
+    for name in new_element_names {
+        let end_tag = BytesEnd::new(name.clone());
+        new_elements.push(Event::End(end_tag));
+    }
+
+    new_elements
+}
+
+pub fn get_groups(config: &Config) -> Vec<String> {
+    let mut groups = config.split.grouping
+    .values()
+    .flat_map(|labeling| labeling.values().cloned())
+    .chain(config.split.missing.values().cloned())
+    .chain(config.split.existing.values().cloned())
+    .collect::<Vec<String>>();
+    groups.sort_unstable();
+    groups.dedup();
+
+    groups.push(config.filter.residue.clone());
+    groups.push(config.split.default.clone());
+    groups
+}
+
+/// Writes XML events above the split level to the output of a group.
+pub fn write_events<S: Sink + ?Sized>(
+    sink: &mut S,
+    group: &str,
+    xml_events: &[Event],
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String
+) -> Result<()> {
+    sink.write_frame(xml_events).map_err(|err| Error::Io {
+        msg: get_msg(msg_config, "writing_event_failed", lang),
+        path: group.to_string(),
+        source: err,
+    })
+}
+
+/// Writes the XML events of a complete split element to the output of its group.
+pub fn write_element<S: Sink + ?Sized>(
+    sink: &mut S,
+    group: &str,
+    xml_events: &[Event],
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String
+) -> Result<()> {
+    sink.write_element(xml_events).map_err(|err| Error::Io {
+        msg: get_msg(msg_config, "writing_event_failed", lang),
+        path: group.to_string(),
+        source: err,
+    })
+}
+
+pub fn get_config(path_str: &str) -> Result<Config> {
+    let config_error = |msg: &str, detail: String| Error::Config {
+        msg: msg.to_string(),
+        path: path_str.to_string(),
+        detail,
+    };
+    let config_str = fs::read_to_string(Path::new(path_str))
+    .map_err(|err| config_error("Can't read configuration file", err.to_string()))?;
+    parse_config(&config_str).map_err(|problems| Error::InvalidConfig {
+        msg: "Invalid configuration".to_string(),
+        path: path_str.to_string(),
+        problems,
+    })
+}
+
+pub fn get_msg_config(path_str: &str) -> Result<HashMap<String, HashMap<String, String>>> {
+    let config_error = |msg: &str, detail: String| Error::Config {
+        msg: msg.to_string(),
+        path: path_str.to_string(),
+        detail,
+    };
+    let msg_config_str = fs::read_to_string(Path::new(path_str))
+    .map_err(|err| config_error("Can't read message configuration file", err.to_string()))?;
+    toml::from_str(&msg_config_str)
+    .map_err(|err| config_error("Unable to parse configuration", err.to_string()))
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
+use std::path::Path;
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The extension appended to the names of compressed files (including the dot).
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Determines the compression of a file by its extension.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(Compression::Gzip.extension()) {
+            Compression::Gzip
+        } else if path.ends_with(Compression::Zstd.extension()) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The value of the `Content-Encoding` header for uploads of compressed files.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Removes the extension of a compressed file, e. g. `invoices.xml.gz` ➔ `invoices.xml`.
+    pub fn strip(path: &str) -> &str {
+        path.strip_suffix(Compression::from_path(path).extension()).unwrap_or(path)
+    }
+}
+
+/// A writer that has to be finished explicitly when all data has been written.
+pub trait Finish: Write {
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> Finish for BufWriter<W> {}
+
+impl Finish for io::Sink {}
+
+impl Finish for Vec<u8> {}
+
+/// A writer which compresses the output if required. Compressed files are only complete
+/// after `finish` has been called.
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W, compression: Compression) -> Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(writer),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(writer, 0)?),
+        })
+    }
+}
+
+impl<W: Finish> Finish for Encoder<W> {
+    /// Writes the remaining compressed data and finishes the underlying writer.
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.finish(),
+            Encoder::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().finish()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().finish()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Encoder::Plain(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decompresses gzip or zstd input while it is read; the compression is detected by the magic bytes
+/// at the beginning of the input. Uncompressed input is passed through unchanged.
+pub fn decompress<R: BufRead + 'static>(mut reader: R) -> Result<Box<dyn BufRead>> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if head.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(reader)?)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Opens an original file, which may be compressed with gzip or zstd.
+pub fn open_original(path: &Path) -> Result<Box<dyn BufRead>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const XML: &str = "<?xml version=\"1.0\"?><payments><invoice>INV-1</invoice></payments>";
+
+    fn compress(compression: Compression) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new(), compression).unwrap();
+        encoder.write_all(XML.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        match encoder {
+            Encoder::Plain(data) => data,
+            Encoder::Gzip(encoder) => encoder.finish().unwrap(),
+            Encoder::Zstd(encoder) => encoder.finish().unwrap(),
+        }
+    }
+
+    #[test]
+    fn compressed_input_is_detected_and_decompressed() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let data = compress(compression);
+            assert_eq!(data.starts_with(XML.as_bytes()), compression == Compression::None);
+            let mut decompressed = String::new();
+            decompress(io::Cursor::new(data)).unwrap().read_to_string(&mut decompressed).unwrap();
+            assert_eq!(decompressed, XML, "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn concatenated_gzip_members_are_read() {
+        let data = [compress(Compression::Gzip), compress(Compression::Gzip)].concat();
+        let mut decompressed = String::new();
+        decompress(io::Cursor::new(data)).unwrap().read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, XML.repeat(2));
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(Compression::from_path("invoices.xml.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("invoices.xml.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("invoices.xml"), Compression::None);
+        assert_eq!(Compression::strip("invoices.xml.gz"), "invoices.xml");
+        assert_eq!(Compression::strip("invoices.xml.zst"), "invoices.xml");
+        assert_eq!(Compression::strip("invoices.xml"), "invoices.xml");
+    }
+}
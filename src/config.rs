@@ -1,8 +1,10 @@
+use std::collections::HashMap;
 use serde::Deserialize;
 use crate::transformations::transformer::*;
 use crate::transformations::filter::*;
 use crate::transformations::split::*;
-use crate::upload::*;
+use crate::output::*;
+use crate::compression::Compression;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Dirs {
@@ -38,12 +40,47 @@ pub struct Email {
     pub message: EmailMessage,
 }
 
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Batch {
+    pub active: bool,
+    #[serde(default)]
+    pub order: String,
+    #[serde(default)]
+    pub pattern: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Watch {
+    pub interval: u64,
+    pub stable_for: u64,
+    pub marker: String,
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Watch {
+            interval: 5,
+            stable_for: 10,
+            marker: String::new(),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
     pub lang: String,
     pub history_size: usize,
+    #[serde(default)]
+    pub history_compression: Compression,
+    #[serde(default)]
     pub consistency_check: bool,
+    #[serde(default)]
     pub inconsistency_notification: bool,
+    #[serde(default)]
+    pub batch: Batch,
+    #[serde(default)]
+    pub watch: Watch,
     pub dirs: Dirs,
     pub timeformats: Timeformat,
     pub email: Email,
@@ -52,9 +89,53 @@ pub struct Settings {
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub element: String,
+    #[serde(default)]
+    pub namespaces: HashMap<String, String>,
     pub filter: Filter,
     pub split: Split,
+    #[serde(default)]
+    pub outputs: HashMap<String, Output>,
+    #[serde(default)]
     pub transformations: Vec<Transformation>,
+    #[serde(default)]
     pub uploads: Vec<Upload>,
     pub settings: Settings,
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Upload {
+    pub active: bool,
+    pub timeout: f32,
+    pub protocol: String,
+    pub server: String,
+    pub path: String,
+    pub user: String,
+    pub key: String,
+    pub pubkey: String,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub schema: String,
+}
+
+impl Upload {
+    /// Decides whether files with the given prefix are to be uploaded by this upload procedure.
+    pub fn accepts(&self, prefix: &String, config: &Config) -> bool {
+        match (self.include.is_empty(), self.exclude.is_empty()) {
+            (true, true) => {
+                prefix != &config.filter.residue && prefix != &config.split.default
+            }
+            (true, false) => {
+                !self.exclude.contains(prefix)
+            }
+            (false, true) => {
+                self.include.contains(prefix)
+            }
+            (false, false) => {
+                self.include.contains(prefix) && !self.exclude.contains(prefix)
+            }
+        }
+    }
+}
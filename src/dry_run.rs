@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use log::{error, info};
+use quick_xml::writer::Writer;
+
+use xtract::transformations::transform::Report;
+use xtract::pipeline::Pipeline;
+use xtract::transformations::trace::Selection;
+use xtract::config::*;
+use crate::utils::*;
+use xtract::compression::decompress;
+use xtract::error::{Error, Result};
+
+/// Filters, splits and transforms the original file(s) without writing, uploading or archiving anything
+/// and prints a report on what a regular run would do with each of them.
+pub fn dry_run(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, input: Option<&str>) -> Result<()> {
+    let lang = &config.settings.lang;
+    let pipeline = Pipeline::new(config, msg_config).inspect_err(|err| error!("{err}"))?;
+    for (name, reader) in get_inputs(config, msg_config, input)? {
+        let msg = get_msg(msg_config, "processing_original", lang);
+        info!("{msg}: {name}");
+        let mut writers = get_sinks(config);
+        match pipeline.run(reader, &mut writers) {
+            Ok(report) => println!("{}", format_report(&name, &report, config, msg_config)),
+            Err(err) => {
+                let msg = get_msg(msg_config, "transformation_failed", lang);
+                error!("{msg}: {name} - {err}");
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Filters, splits and transforms the original file(s) like a dry run and logs the filter, grouping
+/// and transformation decisions for the split elements chosen by the selection.
+pub fn explain(
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    input: Option<&str>,
+    selection: &Selection
+) -> Result<()> {
+    let lang = &config.settings.lang;
+    let pipeline = Pipeline::new(config, msg_config).inspect_err(|err| error!("{err}"))?.trace(selection.clone());
+    for (name, reader) in get_inputs(config, msg_config, input)? {
+        let msg = get_msg(msg_config, "processing_original", lang);
+        info!("{msg}: {name}");
+        let mut writers = get_sinks(config);
+        if let Err(err) = pipeline.run(reader, &mut writers) {
+            let msg = get_msg(msg_config, "transformation_failed", lang);
+            error!("{msg}: {name} - {err}");
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+fn get_inputs(
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    input: Option<&str>
+) -> Result<Vec<(String, Box<dyn BufRead>)>> {
+    match input {
+        Some("-") => Ok(vec![("stdin".to_string(), decompress_input("stdin", io::stdin().lock(), config, msg_config)?)]),
+        Some(path) => Ok(vec![(path.to_string(), open_input(path, config, msg_config)?)]),
+        None => {
+            let original_files = get_original(config, msg_config).inspect_err(|err| error!("{err}"))?;
+            original_files
+            .into_iter()
+            .map(|original_file| {
+                let path = format!("{}/{}", config.settings.dirs.original, original_file);
+                let reader = open_input(&path, config, msg_config)?;
+                Ok((original_file, reader))
+            })
+            .collect()
+        }
+    }
+}
+
+/// Creates a writer discarding the output for each group.
+fn get_sinks(config: &Config) -> HashMap<String, Writer<io::Sink>> {
+    get_groups(config)
+    .into_iter()
+    .map(|group| (group, Writer::new(io::sink())))
+    .collect()
+}
+
+/// Compiles the report of a dry run: the number of split elements per group together with the
+/// file they would be written to and the uploads that would receive it, the filter rules that sent
+/// split elements to the residue, the split elements with conflicting labels and the number of times
+/// each transformation rule was applied.
+fn format_report(
+    name: &str,
+    report: &Report,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> String {
+    let lang = &config.settings.lang;
+    let output_paths = get_output_paths(config, Path::new(name).file_name().and_then(|n| n.to_str()).unwrap_or(name));
+    let mut lines: Vec<String> = vec![format!("{}: {}", get_msg(msg_config, "dry_run_report", lang), name)];
+
+    lines.push(format!("\n{}:", get_msg(msg_config, "elements_per_group", lang)));
+    for group in get_groups(config) {
+        let count = report.elements.get(&group).copied().unwrap_or(0);
+        let filename = output_paths[&group].rsplit("/").next().unwrap_or_default().to_string();
+        let uploads: Vec<String> = config.uploads
+        .iter()
+        .filter(|upload| upload.active && upload.accepts(&group, config))
+        .map(|upload| format!("{}://{}{}/", upload.protocol.to_lowercase(), upload.server, upload.path))
+        .collect();
+        let uploads = if uploads.is_empty() {
+            get_msg(msg_config, "no_upload", lang)
+        } else {
+            uploads.join(", ")
+        };
+        lines.push(format!("  • {group}: {count} ➔ {filename} ➔ {uploads}"));
+    }
+
+    lines.push(format!("\n{}:", get_msg(msg_config, "residue_reasons", lang)));
+    for (index, violations) in &report.residue {
+        lines.push(format!("  • #{}: {}", index, violations.join("; ")));
+    }
+
+    lines.push(format!("\n{}:", get_msg(msg_config, "conflicting_labels", lang)));
+    for (index, labels) in &report.conflicts {
+        lines.push(format!("  • #{}: {}", index, labels.join(", ")));
+    }
+
+    lines.push(format!("\n{}:", get_msg(msg_config, "transformations_applied", lang)));
+    for (number, (target, fired)) in report.transformations.iter().enumerate() {
+        lines.push(format!("  • {}. {}: {}", number + 1, target, fired));
+    }
+    lines.join("\n")
+}
+
+fn open_input(path: &str, config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Result<Box<dyn BufRead>> {
+    match File::open(path) {
+        Ok(file) => decompress_input(path, BufReader::new(file), config, msg_config),
+        Err(err) => Err(input_error(path, err, config, msg_config)),
+    }
+}
+
+fn decompress_input<R: BufRead + 'static>(
+    name: &str,
+    reader: R,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> Result<Box<dyn BufRead>> {
+    decompress(reader).map_err(|err| input_error(name, err, config, msg_config))
+}
+
+fn input_error(name: &str, err: io::Error, config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Error {
+    let error = Error::Io {
+        msg: get_msg(msg_config, "reading_original_failed", &config.settings.lang),
+        path: name.to_string(),
+        source: err,
+    };
+    error!("{error}");
+    error
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::io;
+
+use crate::validation::Problem;
+
+/// Exit code for a missing, unreadable or invalid configuration.
+pub const EXIT_CONFIG: i32 = 2;
+/// Exit code if there is no original file to process or more than one without batch mode.
+pub const EXIT_ORIGINAL: i32 = 3;
+/// Exit code for files or directories that can't be read, written or archived.
+pub const EXIT_IO: i32 = 4;
+/// Exit code for original files that aren't well-formed XML.
+pub const EXIT_XML: i32 = 5;
+/// Exit code for transformation values that can't be evaluated.
+pub const EXIT_EVALUATION: i32 = 6;
+/// Exit code for failed uploads.
+pub const EXIT_UPLOAD: i32 = 7;
+/// Exit code for e-mail reports that can't be sent.
+pub const EXIT_MAIL: i32 = 8;
+/// Exit code for golden-file test cases whose output differs from the expected output.
+pub const EXIT_TEST: i32 = 9;
+/// Exit code for output files that don't match their XML schema.
+pub const EXIT_SCHEMA: i32 = 10;
+
+/// The errors that end the processing of an original file or a whole run. Besides its context,
+/// each error carries the (localized) message under which it is logged and reported.
+#[derive(Debug)]
+pub enum Error {
+    /// A configuration file can't be read or parsed, or a setting is invalid;
+    /// `path` is the configuration file or the setting concerned.
+    Config { msg: String, path: String, detail: String },
+    /// The configuration file `path` contains the listed problems.
+    InvalidConfig { msg: String, path: String, problems: Vec<Problem> },
+    /// There is no original file to process, or more than one without batch mode.
+    Original { msg: String, dir: String, detail: String },
+    /// A file or directory can't be read, written or archived.
+    Io { msg: String, path: String, source: io::Error },
+    /// The original file isn't well-formed XML; `position` is the byte offset of the error.
+    Xml { msg: String, position: u64, detail: String },
+    /// A transformation value can't be evaluated; `rule` is the number of the transformation rule (starting at 1).
+    Evaluation { msg: String, rule: usize, target: String, detail: String },
+    /// A file can't be uploaded to a server.
+    Upload { msg: String, file: String, server: String, detail: String },
+    /// An e-mail report can't be built or sent.
+    Mail { msg: String, detail: String },
+    /// An output file doesn't match the XML schema (XSD) of its group or of an upload.
+    Schema { msg: String, file: String, schema: String, detail: String },
+    /// Golden-file test cases failed; `failed` of `total` cases in `dir`.
+    Test { msg: String, dir: String, failed: usize, total: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The exit code signalling this kind of error to the caller (e. g. a scheduler).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config { .. } | Error::InvalidConfig { .. } => EXIT_CONFIG,
+            Error::Original { .. } => EXIT_ORIGINAL,
+            Error::Io { .. } => EXIT_IO,
+            Error::Xml { .. } => EXIT_XML,
+            Error::Evaluation { .. } => EXIT_EVALUATION,
+            Error::Upload { .. } => EXIT_UPLOAD,
+            Error::Mail { .. } => EXIT_MAIL,
+            Error::Schema { .. } => EXIT_SCHEMA,
+            Error::Test { .. } => EXIT_TEST,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config { msg, path, detail } => write!(f, "{msg}: {path} - {detail}"),
+            Error::InvalidConfig { msg, path, problems } => {
+                write!(f, "{msg}: {path}")?;
+                for problem in problems {
+                    write!(f, "\n  • {path}:{problem}")?;
+                }
+                Ok(())
+            }
+            Error::Original { msg, dir, detail } if detail.is_empty() => write!(f, "{msg}: {dir}"),
+            Error::Original { msg, dir, detail } => write!(f, "{msg}: {dir} - {detail}"),
+            Error::Io { msg, path, source } => write!(f, "{msg}: {path} - {source}"),
+            Error::Xml { msg, position, detail } => write!(f, "{msg} (Byte {position}): {detail}"),
+            Error::Evaluation { msg, rule, target, detail } => write!(f, "{msg}: #{rule} ({target}) - {detail}"),
+            Error::Upload { msg, file, server, detail } => write!(f, "{msg}: {file} ➔ {server} - {detail}"),
+            Error::Mail { msg, detail } => write!(f, "{msg}: {detail}"),
+            Error::Schema { msg, file, schema, detail } => write!(f, "{msg}: {file} ({schema}) - {detail}"),
+            Error::Test { msg, dir, failed, total } => write!(f, "{msg}: {dir} ({failed}/{total})"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_documented() {
+        let readme = include_str!("../README.md");
+        let section = readme.split("### Exit codes").nth(1).unwrap();
+        let documented: Vec<i32> = section.lines()
+        .take_while(|line| !line.starts_with('#'))
+        .filter_map(|line| line.strip_prefix("| ")?.split(" |").next()?.parse().ok())
+        .collect();
+        let codes = [0, EXIT_CONFIG, EXIT_ORIGINAL, EXIT_IO, EXIT_XML, EXIT_EVALUATION, EXIT_UPLOAD, EXIT_MAIL, EXIT_TEST, EXIT_SCHEMA];
+        assert_eq!(documented, codes);
+    }
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::common::*;
+use crate::compression::Finish;
+use crate::error::{Error, Result};
+use crate::output::{new_sink, Sink};
+use crate::pipeline::Pipeline;
+
+/// The original XML file of a test case.
+pub const INPUT: &str = "input.xml";
+/// The configuration of a test case.
+pub const CONFIG: &str = "config.toml";
+/// The directory of a test case containing the expected output of each group.
+pub const EXPECTED: &str = "expected";
+
+/// The result of a test case: the differences between the expected and the actual output
+/// of the groups (none if the case passed).
+#[derive(Debug, Clone, Default)]
+pub struct CaseResult {
+    pub name: String,
+    pub failures: Vec<String>,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// An output kept in memory, shared between the sink writing it and the harness comparing it.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Finish for Buffer {}
+
+/// Finds the test cases in a directory: the directory itself if it contains a `config.toml`,
+/// otherwise each of its subdirectories containing one (in alphabetical order).
+pub fn find_cases(dir: &Path) -> Result<Vec<PathBuf>> {
+    if dir.join(CONFIG).is_file() {
+        return Ok(vec![dir.to_path_buf()]);
+    }
+    let io_error = |err: io::Error| Error::Io {
+        msg: "Can't read test case directory".to_string(),
+        path: dir.to_string_lossy().to_string(),
+        source: err,
+    };
+    let mut cases: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.join(CONFIG).is_file() {
+            cases.push(path);
+        }
+    }
+    cases.sort();
+    Ok(cases)
+}
+
+/// Runs all test cases found in a directory (see `find_cases`); differences are reported in `lang`.
+pub fn run_cases(
+    dir: &Path,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String,
+    update: bool
+) -> Result<Vec<CaseResult>> {
+    Ok(find_cases(dir)?
+    .iter()
+    .map(|case| run_case(case, msg_config, lang, update))
+    .collect())
+}
+
+/// Runs a test case: filters, splits and transforms its `input.xml` according to its `config.toml`
+/// and compares the output of each group with the file named after the group in `expected/`
+/// (e. g. `LIB001.xml` or `RESIDUE.json`, without compression). Groups without an expected file must
+/// not receive any split elements. With `update`, the expected files are replaced by the actual output.
+pub fn run_case(
+    dir: &Path,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String,
+    update: bool
+) -> CaseResult {
+    let name = dir.file_name().unwrap_or(dir.as_os_str()).to_string_lossy().to_string();
+    let failures = match compare(dir, msg_config, lang, update) {
+        Ok(failures) => failures,
+        Err(err) => vec![err.to_string()],
+    };
+    CaseResult { name, failures }
+}
+
+fn compare(
+    dir: &Path,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String,
+    update: bool
+) -> Result<Vec<String>> {
+    let config = get_config(&dir.join(CONFIG).to_string_lossy())?;
+    let input_path = dir.join(INPUT);
+    let input = fs::read(&input_path).map_err(|err| Error::Io {
+        msg: get_msg(msg_config, "reading_original_failed", lang),
+        path: input_path.to_string_lossy().to_string(),
+        source: err,
+    })?;
+
+    let pipeline = Pipeline::new(&config, msg_config)?;
+    let mut buffers: HashMap<String, Buffer> = HashMap::new();
+    let mut sinks: HashMap<String, Box<dyn Sink>> = HashMap::new();
+    for group in pipeline.groups() {
+        let buffer = Buffer::default();
+        let output = config.outputs.get(&group).cloned().unwrap_or_default();
+        let sink = new_sink(buffer.clone(), &output).map_err(|err| Error::Io {
+            msg: get_msg(msg_config, "writing_file_failed", lang),
+            path: group.clone(),
+            source: err,
+        })?;
+        buffers.insert(group.clone(), buffer);
+        sinks.insert(group, sink);
+    }
+    let report = pipeline.run(&input[..], &mut sinks)?;
+    for (group, sink) in sinks.iter_mut() {
+        sink.finish().map_err(|err| Error::Io {
+            msg: get_msg(msg_config, "writing_file_failed", lang),
+            path: group.clone(),
+            source: err,
+        })?;
+    }
+
+    let mut failures: Vec<String> = Vec::new();
+    for group in pipeline.groups() {
+        let output = config.outputs.get(&group).cloned().unwrap_or_default();
+        let expected_path = dir.join(EXPECTED).join(format!("{}.{}", group, output.format.extension()));
+        let actual = buffers[&group].0.borrow().clone();
+        let count = report.elements.get(&group).copied().unwrap_or(0);
+        if update {
+            update_expected(&expected_path, &actual, count, msg_config, lang)?;
+            continue;
+        }
+        match fs::read(&expected_path) {
+            Ok(expected) => {
+                if let Some(difference) = describe_difference(&expected, &actual) {
+                    let msg = get_msg(msg_config, "output_differs", lang);
+                    failures.push(format!("{group}: {msg} - {difference}"));
+                }
+            }
+            Err(_) if count > 0 => {
+                let msg = get_msg(msg_config, "unexpected_output", lang);
+                failures.push(format!("{group}: {msg} ({count})"));
+            }
+            Err(_) => (),
+        }
+    }
+    Ok(failures)
+}
+
+/// Writes the actual output of a group as its expected output; groups without split elements
+/// don't get an expected file.
+fn update_expected(
+    path: &Path,
+    actual: &[u8],
+    count: usize,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String
+) -> Result<()> {
+    let io_error = |err: io::Error| Error::Io {
+        msg: get_msg(msg_config, "writing_file_failed", lang),
+        path: path.to_string_lossy().to_string(),
+        source: err,
+    };
+    if count == 0 {
+        if path.exists() {
+            fs::remove_file(path).map_err(io_error)?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+    fs::write(path, actual).map_err(io_error)
+}
+
+/// Describes the first difference between the expected and the actual output: its byte offset
+/// and the surrounding text of both outputs.
+fn describe_difference(expected: &[u8], actual: &[u8]) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let position = expected.iter().zip(actual).take_while(|(a, b)| a == b).count();
+    let excerpt = |bytes: &[u8]| {
+        let start = position.saturating_sub(40);
+        let end = (position + 40).min(bytes.len());
+        format!("{:?}", String::from_utf8_lossy(&bytes[start.min(end)..end]))
+    };
+    Some(format!(
+        "Byte {}:\n    expected: {}\n    actual:   {}",
+        position, excerpt(expected), excerpt(actual)
+    ))
+}
@@ -0,0 +1,23 @@
+//! The filter, split and transform engine of XtracT. A `Pipeline` reads XML from any `BufRead`
+//! and writes the split elements of each group to a `Sink`; writing files to the history,
+//! uploads and e-mail reports are left to the caller.
+
+pub mod config;
+pub mod transformations;
+pub mod output;
+pub mod compression;
+pub mod error;
+pub mod common;
+pub mod pipeline;
+pub mod golden;
+pub mod validation;
+
+pub use config::Config;
+pub use transformations::filter::Filter;
+pub use transformations::split::Split;
+pub use transformations::transformer::Transformation;
+pub use transformations::transform::Report;
+pub use transformations::trace::Selection;
+pub use output::Sink;
+pub use pipeline::Pipeline;
+pub use error::{Error, Result};
@@ -1,211 +1,276 @@
 use std::env;
-use std::fs::create_dir_all;
+use std::fs::{self, create_dir_all, File};
+use std::io;
 use std::path::Path;
+use std::process::exit;
 use std::collections::HashMap;
 use chrono::Local;
-use quick_xml::events::Event;
-use log::{error, info};
-use log4rs;
+use clap::Parser;
+use log::{error, info, LevelFilter};
+use log4rs::append::console::ConsoleAppender;
+use log4rs::config::{Appender, Root};
 
-use transformations::transform::*;
-use config::*;
+use xtract::config::*;
+use xtract::error::*;
+use xtract::validation::check_config;
+use xtract::pipeline::Pipeline;
+use xtract::transformations::trace::Selection;
 use utils::*;
-use upload::*;
+use process::*;
+use watch::*;
+use dry_run::*;
+use cli::*;
 
-mod transformations;
-mod config;
 mod utils;
 mod upload;
+mod schema;
+mod process;
+mod watch;
+mod dry_run;
+mod cli;
 
 fn main() {
-    match dotenvy::dotenv() {
-        Ok(_) => {}
-        Err(err) => {
-            let msg = "Error: Can't import environment variables";
-            eprintln!("{msg}: {err}");
-            return;
+    let cli = Cli::parse();
+
+    // The .env file is optional, so only an existing but unreadable file is reported:
+    if let Err(err) = dotenvy::dotenv() {
+        if !err.not_found() {
+            eprintln!("WARNING: Can't import environment variables: {err}");
         }
     }
 
-    let log4rs_path: String;
-    let config_path: String;
-    let msg_config_path: String;
-    
-    match env::vars().find(|(k, _)| k == "LOG4RS") {
-        None => {
-            eprintln!("{}", "ERROR: No environment variable for log4rs configuration path.");
-            return;
+    match cli.log_config.clone().or_else(|| env::var("LOG4RS").ok()) {
+        Some(log4rs_path) => {
+            if let Err(err) = log4rs::init_file(Path::new(&log4rs_path), Default::default()) {
+                eprintln!("ERROR: Can't read log4rs configuration file: {err}");
+                init_console_logging();
+            }
         }
-        Some(entry) => log4rs_path = entry.1,
+        None => init_console_logging(),
     }
 
-    match env::vars().find(|(k, _)| k == "CONFIG") {
-        None => {
-            error!("{}", "No environment variable for configuration path.");
-            return;
+    // The test cases bring their own configuration, so neither configuration file is required:
+    if let Some(Command::Test { dir, update }) = &cli.command {
+        let msg_config = cli.messages.clone().or_else(|| env::var("MSG_CONFIG").ok())
+        .and_then(|path| get_msg_config(&path).ok())
+        .unwrap_or_default();
+        let lang = cli.config.clone().or_else(|| env::var("CONFIG").ok())
+        .and_then(|path| get_config(&path).ok())
+        .map_or("en".to_string(), |config| config.settings.lang);
+        if let Err(err) = test(Path::new(dir), *update, &msg_config, &lang) {
+            exit(err.exit_code());
         }
-        Some(entry) => config_path = entry.1,
+        return;
     }
 
-    match env::vars().find(|(k, _)| k == "MSG_CONFIG") {
+    let config_path = match cli.config.clone().or_else(|| env::var("CONFIG").ok()) {
+        Some(path) => path,
         None => {
-            error!("{}", "No environment variable for message configuration path.");
-            return;
+            error!("No configuration path: use the --config option or the CONFIG environment variable.");
+            exit(EXIT_CONFIG);
         }
-        Some(entry) => msg_config_path = entry.1,
-    }
+    };
+
+    let msg_config_path = match cli.messages.clone().or_else(|| env::var("MSG_CONFIG").ok()) {
+        Some(path) => path,
+        None => {
+            error!("No message configuration path: use the --messages option or the MSG_CONFIG environment variable.");
+            exit(EXIT_CONFIG);
+        }
+    };
+
+    let config = get_config(&config_path).unwrap_or_else(|err| {
+        error!("{err}");
+        exit(err.exit_code());
+    });
+    let lang = &config.settings.lang;
+
+    let msg_config: HashMap<String, HashMap<String, String>> = get_msg_config(&msg_config_path).unwrap_or_else(|err| {
+        error!("{err}");
+        exit(err.exit_code());
+    });
 
-    match log4rs::init_file(Path::new(&log4rs_path), Default::default()) {
-        Ok(_) => (),
-        Err(_) => {
-            eprintln!("ERROR: Can't read log4rs configuration file.");
+    // Errors are logged where they occur, so they only have to be mapped to the exit code here:
+    let result = match cli.command.clone().unwrap_or(Command::Run) {
+        Command::Run => run(&config, &msg_config, cli.input.as_deref()),
+        Command::Check => {
+            check_consistency(&config, &msg_config, lang);
+            Ok(())
+        }
+        Command::DryRun => dry_run(&config, &msg_config, cli.input.as_deref()),
+        Command::Explain { index, key } => {
+            let selection = Selection { indices: index, key };
+            explain(&config, &msg_config, cli.input.as_deref(), &selection)
+        }
+        Command::Validate => validate(&config, &msg_config, &config_path),
+        Command::Watch => {
+            if config.settings.consistency_check {
+                check_consistency(&config, &msg_config, lang);
+            }
+            watch(&config, &msg_config)
         }
+        Command::Test { .. } => Ok(()),
+    };
+    if let Err(err) = result {
+        exit(err.exit_code());
     }
+}
 
-    let config = get_config(&config_path);
+fn validate(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, config_path: &str) -> Result<()> {
     let lang = &config.settings.lang;
+    let source = fs::read_to_string(config_path).map_err(|err| {
+        let error = Error::Config { msg: "Can't read configuration file".to_string(), path: config_path.to_string(), detail: err.to_string() };
+        error!("{error}");
+        error
+    })?;
+    let problems = check_config(&source, config, msg_config, lang);
+    if problems.is_empty() {
+        let msg = get_msg(msg_config, "configuration_valid", lang);
+        info!("{msg}: {config_path}");
+        Ok(())
+    } else {
+        let msg = get_msg(msg_config, "configuration_invalid", lang);
+        let error = Error::InvalidConfig { msg, path: config_path.to_string(), problems };
+        error!("{error}");
+        Err(error)
+    }
+}
 
-    let msg_config: HashMap<String, HashMap<String, String>> = get_msg_config(&msg_config_path);
+/// Runs the golden-file test cases in `dir` and prints the result of each case.
+fn test(dir: &Path, update: bool, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) -> Result<()> {
+    let results = xtract::golden::run_cases(dir, msg_config, lang, update).map_err(|err| {
+        error!("{err}");
+        err
+    })?;
+    for result in &results {
+        if update && result.passed() {
+            let msg = get_msg(msg_config, "expected_output_updated", lang);
+            println!("✅ {}: {}", result.name, msg);
+        } else if result.passed() {
+            let msg = get_msg(msg_config, "test_case_passed", lang);
+            println!("✅ {}: {}", result.name, msg);
+        } else {
+            let msg = get_msg(msg_config, "test_case_failed", lang);
+            println!("❌ {}: {}\n  • {}", result.name, msg, result.failures.join("\n  • "));
+        }
+    }
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    let msg = get_msg(msg_config, "test_summary", lang);
+    println!("{}: {}/{}", msg, results.len() - failed, results.len());
+    if failed > 0 {
+        let msg = get_msg(msg_config, "test_cases_failed", lang);
+        return Err(Error::Test { msg, dir: dir.to_string_lossy().to_string(), failed, total: results.len() });
+    }
+    Ok(())
+}
 
+fn init_console_logging() {
+    let stdout = ConsoleAppender::builder().build();
+    let log_config = log4rs::Config::builder()
+    .appender(Appender::builder().build("stdout", Box::new(stdout)))
+    .build(Root::builder().appender("stdout").build(LevelFilter::Info));
+    if let Ok(log_config) = log_config {
+        let _ = log4rs::init_config(log_config);
+    }
+}
+
+/// Processes the original file(s) and sends the report. The exit code is determined by the first original file
+/// whose processing failed, if any, and otherwise by a report that couldn't be sent.
+fn run(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, input: Option<&str>) -> Result<()> {
+    let lang = &config.settings.lang;
     let hist_dir = Path::new(&config.settings.dirs.history);
     let time_format = &config.settings.timeformats.history_folder;
     let storage_period = &config.settings.history_size;
-    let timestamp: String = Local::now().format(&time_format).to_string();
+    let now = Local::now();
+    let timestamp: String = now.format(time_format).to_string();
 
-    check_history(hist_dir, storage_period, time_format, &timestamp, &msg_config, lang);
+    check_history(hist_dir, storage_period, time_format, &timestamp, msg_config, lang);
 
     if config.settings.consistency_check {
-        check_consistency(&config, &msg_config, lang);
+        check_consistency(config, msg_config, lang);
     }
 
-    match get_original(&config, &msg_config) {
-        Ok(original_file) => {
-            let str_path_to_original = format!("{}/{}", config.settings.dirs.original, original_file);
-            let path_to_original: &Path = Path::new(&str_path_to_original);
-            
-            let current_history = &hist_dir.join(&timestamp);
-            match create_dir_all(current_history) {
-                Ok(_) => {
-                    let msg = get_msg(&msg_config, "history_creation_successful", lang);
-                    info!("{msg}: {timestamp}");
-                }
-                Err(err) => {
-                    let msg = get_msg(&msg_config, "history_creation_failed", lang);
-                    error!("{msg}: {timestamp} - {err}");
-                    panic!();
-                }
+    // The rules are compiled once for all original files, and relative dates are anchored to the run timestamp:
+    let pipeline = Pipeline::new(config, msg_config).inspect_err(|err| {
+        error!("{err}");
+        let _ = send_mail(config, msg_config, err.to_string());
+    })?.at(now.naive_local());
+
+    let original_files: Vec<String> = match input {
+        Some(_) => Vec::new(),
+        None => match get_original(config, msg_config) {
+            Ok(original_files) => original_files,
+            Err(err) => {
+                let msg = get_msg(msg_config, "reading_original_failed", lang);
+                let error_msg = format!("{msg}: {err}.");
+                let addition = format!("{}.", get_msg(msg_config, "process_cancelled", lang));
+                error!("{error_msg} {addition}");
+                let _ = send_mail(config, msg_config, error_msg + "\n\n" + &addition);
+                return Err(err);
             }
+        }
+    };
 
-            match transform(path_to_original, &config, &msg_config) {
-                Ok(transformed) => {
-                    let file_stem = Path::new(&original_file).file_stem().unwrap().to_str().unwrap();
-                    let timestamp: String = chrono::Local::now()
-                    .format(&config.settings.timeformats.files).to_string();                    
-                    let revised: HashMap<String, &Vec<Event>> = transformed.iter()
-                    .map(
-                        |(group, event_list)| (
-                            format!(
-                                "{}/{}_{}_{}.xml",
-                                config.settings.dirs.transformed,
-                                group,
-                                file_stem,
-                                timestamp
-                            ),
-                            event_list
-                        )
-                    ).collect();
-
-                    let mut uploads: HashMap<String, Vec<Upload>> = HashMap::new();
-                    let mut archiving_failed: Vec<String> = Vec::new();
-                    for (file_path_str, xml) in revised {
-                        match write_xml(xml, &file_path_str, &msg_config, &config) {
-                            Ok(_) => {
-                                let path_split: Vec<&str> = file_path_str.split("/").collect();
-                                let filename = path_split.last().unwrap();
-                                let failed = run_uploads(&config, &msg_config, &file_path_str);
-                                uploads.insert(filename.to_string(), failed.clone());
-                                if failed.is_empty() {
-                                    let file_path = Path::new(&file_path_str);
-                                    let file_history = &current_history.join(&filename);
-                                    match archive(file_path, &file_history) {
-                                        Ok(_) => {
-                                            let msg = get_msg(&msg_config, "archiving_successful", lang);
-                                            info!("{msg}: {filename} ✅");
-                                        }
-                                        Err(err) => {
-                                            let msg = get_msg(&msg_config, "archiving_failed", lang);
-                                            error!("{msg}: {filename} ❌ - {err}");
-                                            archiving_failed.push(filename.to_string());
-                                        }
-                                    }
-                                } else {
-                                    let msg = get_msg(&msg_config, "archiving_prevented", lang);
-                                    error!("{msg}: {file_path_str}");   
-                                }
-                            }
-                            Err(err) => {
-                                let msg = get_msg(&msg_config, "archiving_prevented", lang);
-                                error!("{msg}: {file_path_str} - {err}");
-                            }
-                        }
-                    }
-
-                    let uploads_failed: HashMap<String, Vec<Upload>> = uploads
-                    .into_iter()
-                    .filter(|(_k, v)| !v.is_empty())
-                    .collect();
-
-                    if !uploads_failed.is_empty() {
-                        let list = uploads_failed
-                        .into_iter().map(|(k, v)| format!("{}: {:?}", k, v)).collect::<Vec<String>>().join("\n");
-                        let msg = get_msg(&msg_config, "upload_report", lang);
-                        let upload_report = msg + ":\n\n" + &list;
-                        send_mail(&config, &msg_config, upload_report);
-                    }
-                    if !archiving_failed.is_empty() {
-                        let msg = get_msg(&msg_config, "archiving_report", lang);
-                        let list = archiving_failed.join("\n");
-                        let archiving_report = msg + ":\n\n" + &list;
-                        send_mail(&config, &msg_config, archiving_report);
-                    }
-
-                    let original_history = &current_history.join(&original_file);
-                    match archive(path_to_original, original_history) {
-                        Ok(_) => {
-                            let msg = get_msg(&msg_config, "archiving_successful", lang);
-                            info!("{msg}: {original_file} ✅");
-                        }
-                        Err(err) => {
-                            let msg = get_msg(&msg_config, "archiving_failed", lang);
-                            let error_msg = format!("{msg}: {original_file} ❌ - {err}");
-                            let addition = get_msg(&msg_config, "archiving_original_failed", lang);
-                            error!("{error_msg}. {addition}");
-                            send_mail(&config, &msg_config, error_msg + "\n\n" + &addition);
-                            panic!();
-                        }
-                    }
-                }
+    let current_history = &hist_dir.join(&timestamp);
+    match create_dir_all(current_history) {
+        Ok(_) => {
+            let msg = get_msg(msg_config, "history_creation_successful", lang);
+            info!("{msg}: {timestamp}");
+        }
+        Err(err) => {
+            let error = Error::Io {
+                msg: get_msg(msg_config, "history_creation_failed", lang),
+                path: current_history.to_string_lossy().to_string(),
+                source: err,
+            };
+            error!("{error}");
+            let _ = send_mail(config, msg_config, error.to_string());
+            return Err(error);
+        }
+    }
+
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    match input {
+        Some("-") => {
+            // The input from stdin is saved in the history first, so that it is archived like any original file:
+            let file_timestamp: String = Local::now().format(&config.settings.timeformats.files).to_string();
+            let path_to_original = current_history.join(format!("stdin_{}.xml", file_timestamp));
+            let spooled = File::create(&path_to_original)
+            .and_then(|mut file| io::copy(&mut io::stdin().lock(), &mut file));
+            match spooled {
+                Ok(_) => outcomes.push(process_original(&path_to_original, false, current_history, &pipeline, config, msg_config)),
                 Err(err) => {
-                    let msg = get_msg(&msg_config, "transformation_failed", lang);
-                    let error_msg = format!("{msg}: {original_file} - {err}");
-                    let addition = format!(
-                        "{}. {}.",
-                        get_msg(&msg_config, "transforming_original_failed", lang),
-                        get_msg(&msg_config, "process_cancelled", lang)
-                    );
-                    error!("{error_msg}. {addition}");
-                    send_mail(&config, &msg_config, error_msg + "\n\n" + &addition);
-                    panic!();
+                    let error = Error::Io {
+                        msg: get_msg(msg_config, "reading_original_failed", lang),
+                        path: "stdin".to_string(),
+                        source: err,
+                    };
+                    error!("{error}");
+                    let mut outcome = Outcome::new("stdin");
+                    outcome.fail(error.to_string(), error);
+                    outcomes.push(outcome);
                 }
             }
         }
-        Err(err) => {
-            let msg = get_msg(&msg_config, "reading_original_failed", lang);
-            let error_msg = format!("{msg}: {err}.");
-            let addition = format!("{}.", get_msg(&msg_config, "process_cancelled", lang));
-            error!("{error_msg} {addition}");
-            send_mail(&config, &msg_config, error_msg + "\n\n" + &addition);
-            panic!();
+        Some(path) => {
+            let msg = get_msg(msg_config, "processing_original", lang);
+            info!("{msg}: {path}");
+            // A file given explicitly is left where it is; only the files of the original directory are archived:
+            outcomes.push(process_original(Path::new(path), false, current_history, &pipeline, config, msg_config));
         }
+        None => {
+            for original_file in &original_files {
+                let msg = get_msg(msg_config, "processing_original", lang);
+                info!("{msg}: {original_file}");
+                let path_to_original = Path::new(&config.settings.dirs.original).join(original_file);
+                outcomes.push(process_original(&path_to_original, true, current_history, &pipeline, config, msg_config));
+            }
+        }
+    }
+    let reported = send_report(&outcomes, config, msg_config);
+
+    match outcomes.into_iter().find_map(|outcome| outcome.into_error()) {
+        Some(err) => Err(err),
+        None => reported,
     }
-}
\ No newline at end of file
+}
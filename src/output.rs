@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+use encoding_rs::{CoderResult, Encoding, UTF_8};
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesDecl, Event};
+use quick_xml::writer::Writer;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::compression::*;
+
+/// The format of the output file of a group.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Xml,
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl Format {
+    /// Determines the format of an output file by its extension (XML if unknown),
+    /// ignoring the extension of a compression.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(Compression::strip(path)).extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Format::Json,
+            Some("ndjson") => Format::Ndjson,
+            Some("csv") => Format::Csv,
+            _ => Format::Xml,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Xml => "xml",
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Xml => "application/xml",
+            Format::Json => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+            Format::Csv => "text/csv",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Output {
+    pub format: Format,
+    pub columns: Vec<String>,
+    pub delimiter: char,
+    pub compression: Compression,
+    pub encoding: String,
+    pub schema: String,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            format: Format::Xml,
+            columns: Vec::new(),
+            delimiter: ',',
+            compression: Compression::None,
+            encoding: String::new(),
+            schema: String::new(),
+        }
+    }
+}
+
+/// Receives the output of a group: the XML events above the split level, which are written
+/// to every group, and the split elements assigned to the group.
+pub trait Sink {
+    fn write_frame(&mut self, events: &[Event]) -> Result<()>;
+    fn write_element(&mut self, events: &[Event]) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+impl<W: Finish> Sink for Writer<W> {
+    fn write_frame(&mut self, events: &[Event]) -> Result<()> {
+        for event in events {
+            self.write_event(event.borrow())?;
+        }
+        Ok(())
+    }
+
+    fn write_element(&mut self, events: &[Event]) -> Result<()> {
+        self.write_frame(events)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.get_mut().finish()
+    }
+}
+
+/// Writes XML like `Writer`, but declares the configured encoding in the XML declaration.
+pub struct XmlSink<W: Finish> {
+    writer: Writer<W>,
+    encoding: String,
+}
+
+impl<W: Finish> XmlSink<W> {
+    pub fn new(out: W, encoding: &str) -> Self {
+        XmlSink { writer: Writer::new(out), encoding: encoding.to_string() }
+    }
+}
+
+impl<W: Finish> Sink for XmlSink<W> {
+    fn write_frame(&mut self, events: &[Event]) -> Result<()> {
+        for event in events {
+            match event {
+                Event::Decl(e) if !self.encoding.is_empty() => {
+                    let version = e.version().map(|version| String::from_utf8_lossy(&version).to_string())
+                    .unwrap_or("1.0".to_string());
+                    let standalone = e.standalone().and_then(|standalone| standalone.ok())
+                    .map(|standalone| String::from_utf8_lossy(&standalone).to_string());
+                    let declaration = BytesDecl::new(&version, Some(&self.encoding), standalone.as_deref());
+                    self.writer.write_event(Event::Decl(declaration))?;
+                }
+                _ => self.writer.write_event(event.borrow())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_element(&mut self, events: &[Event]) -> Result<()> {
+        self.writer.write_frame(events)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish()
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn write_frame(&mut self, events: &[Event]) -> Result<()> {
+        (**self).write_frame(events)
+    }
+
+    fn write_element(&mut self, events: &[Event]) -> Result<()> {
+        (**self).write_element(events)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Writes the split elements as JSON objects, either as a single array (JSON)
+/// or one object per line (NDJSON). The events above the split level are dropped.
+pub struct JsonSink<W: Finish> {
+    out: W,
+    lines: bool,
+    count: usize,
+}
+
+impl<W: Finish> JsonSink<W> {
+    pub fn new(out: W, lines: bool) -> Self {
+        JsonSink { out, lines, count: 0 }
+    }
+}
+
+impl<W: Finish> Sink for JsonSink<W> {
+    fn write_frame(&mut self, _events: &[Event]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_element(&mut self, events: &[Event]) -> Result<()> {
+        let object = to_json(events);
+        if self.lines {
+            writeln!(self.out, "{}", object)?;
+        } else {
+            let separator = if self.count == 0 { "[\n" } else { ",\n" };
+            write!(self.out, "{}{}", separator, object)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if !self.lines {
+            let opening = if self.count == 0 { "[" } else { "" };
+            writeln!(self.out, "{}\n]", opening)?;
+        }
+        self.out.finish()
+    }
+}
+
+/// Writes one row per split element, containing the values found at the configured column paths
+/// (relative to the split element). The first row contains the column paths.
+pub struct CsvSink<W: Finish> {
+    out: W,
+    columns: Vec<String>,
+    delimiter: u8,
+}
+
+impl<W: Finish> CsvSink<W> {
+    pub fn new(out: W, columns: &Vec<String>, delimiter: char) -> Result<Self> {
+        let mut sink = CsvSink { out, columns: columns.clone(), delimiter: csv_delimiter(delimiter)? };
+        sink.write_row(columns)?;
+        Ok(sink)
+    }
+
+    fn write_row<I: IntoIterator<Item = T>, T: AsRef<[u8]>>(&mut self, record: I) -> Result<()> {
+        let mut row = csv::WriterBuilder::new().delimiter(self.delimiter).from_writer(Vec::new());
+        row.write_record(record)?;
+        let row = row.into_inner().map_err(|err| err.into_error())?;
+        self.out.write_all(&row)
+    }
+}
+
+impl<W: Finish> Sink for CsvSink<W> {
+    fn write_frame(&mut self, _events: &[Event]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_element(&mut self, events: &[Event]) -> Result<()> {
+        let values = relative_values(events);
+        let record: Vec<String> = self.columns
+        .iter()
+        .map(|column| values.get(column).map(|found| found.join("|")).unwrap_or_default())
+        .collect();
+        self.write_row(record)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.finish()
+    }
+}
+
+/// Creates the output file of a group in the configured format (XML by default).
+pub fn create_sink(output_path: &str, output: Option<&Output>) -> Result<Box<dyn Sink>> {
+    let output = output.cloned().unwrap_or_default();
+    let file = Encoder::new(BufWriter::new(File::create(output_path)?), output.compression)?;
+    new_sink(file, &output)
+}
+
+/// Creates the sink writing the output of a group in its format and encoding to the given writer
+/// (the compression is up to the writer).
+pub fn new_sink<W: Finish + 'static>(out: W, output: &Output) -> Result<Box<dyn Sink>> {
+    let encoding = output_encoding(&output.encoding)?;
+    let out = Transcoder::new(out, encoding);
+    Ok(match output.format {
+        // The declaration names the encoding actually used, e. g. `windows-1252` for the label `latin1`:
+        Format::Xml if output.encoding.is_empty() => Box::new(XmlSink::new(out, "")),
+        Format::Xml => Box::new(XmlSink::new(out, encoding.name())),
+        Format::Json => Box::new(JsonSink::new(out, false)),
+        Format::Ndjson => Box::new(JsonSink::new(out, true)),
+        Format::Csv => Box::new(CsvSink::new(out, &output.columns, output.delimiter)?),
+    })
+}
+
+/// Returns the byte separating the fields of CSV files, which must be an ASCII character.
+pub fn csv_delimiter(delimiter: char) -> Result<u8> {
+    match u8::try_from(delimiter) {
+        Ok(byte) if byte.is_ascii() => Ok(byte),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported CSV delimiter {:?}", delimiter))),
+    }
+}
+
+/// Looks up the encoding for a label like `ISO-8859-1` or `windows-1252`; an empty label means UTF-8.
+pub fn output_encoding(label: &str) -> Result<&'static Encoding> {
+    if label.is_empty() {
+        return Ok(UTF_8);
+    }
+    match Encoding::for_label(label.as_bytes()) {
+        // encoding_rs can only decode UTF-16, so it can't be used as output encoding:
+        Some(encoding) if encoding.output_encoding() == encoding => Ok(encoding),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported output encoding {:?}", label))),
+    }
+}
+
+/// Encodes the UTF-8 output in the output encoding of a group. Characters that can't be
+/// represented in the output encoding are written as numeric character references.
+pub struct Transcoder<W: Finish> {
+    out: W,
+    encoder: Option<encoding_rs::Encoder>,
+    pending: Vec<u8>,
+}
+
+impl<W: Finish> Transcoder<W> {
+    pub fn new(out: W, encoding: &'static Encoding) -> Self {
+        let encoder = if encoding == UTF_8 { None } else { Some(encoding.new_encoder()) };
+        Transcoder { out, encoder, pending: Vec::new() }
+    }
+
+    fn encode(&mut self, last: bool) -> Result<()> {
+        let Some(encoder) = &mut self.encoder else {
+            return Ok(());
+        };
+        // A multi-byte character may be split between two writes, so an incomplete
+        // sequence at the end is kept until the next write:
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() && !last => err.valid_up_to(),
+            Err(_) => {
+                self.pending = String::from_utf8_lossy(&self.pending).into_owned().into_bytes();
+                self.pending.len()
+            }
+        };
+        let text = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+        let mut rest = text.as_str();
+        let mut encoded: Vec<u8> = Vec::with_capacity(rest.len() + 16);
+        loop {
+            let (result, read, _) = encoder.encode_from_utf8_to_vec(rest, &mut encoded, last);
+            rest = &rest[read..];
+            match result {
+                CoderResult::InputEmpty => break,
+                CoderResult::OutputFull => encoded.reserve(rest.len() + 16),
+            }
+        }
+        self.pending.drain(..valid);
+        self.out.write_all(&encoded)
+    }
+}
+
+impl<W: Finish> Write for Transcoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.encoder.is_none() {
+            return self.out.write(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        self.encode(false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Finish> Finish for Transcoder<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.encode(true)?;
+        self.out.finish()
+    }
+}
+
+fn event_name(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).to_string()
+}
+
+fn event_attributes(e: &quick_xml::events::BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+    .flatten()
+    .map(|attr| (
+        event_name(attr.key.as_ref()),
+        unescape(&String::from_utf8_lossy(&attr.value)).map(|value| value.to_string()).unwrap_or_default()
+    ))
+    .collect()
+}
+
+/// Collects the values of a split element by their paths relative to the split element:
+/// `a/b` for the text of a descendant, `a/b/@attr` for an attribute of a descendant and
+/// `@attr` for an attribute of the split element itself. Repeated paths keep all values.
+fn relative_values(events: &[Event]) -> HashMap<String, Vec<String>> {
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+    let mut path: Vec<String> = Vec::new();
+    for event in events {
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                if path.is_empty() {
+                    // only the last element at the split level (i. e. the split element) counts:
+                    values.clear();
+                }
+                path.push(event_name(e.name().as_ref()));
+                let relative = path[1..].join("/");
+                for (name, value) in event_attributes(e) {
+                    let key = if relative.is_empty() { format!("@{}", name) } else { format!("{}/@{}", relative, name) };
+                    values.entry(key).or_default().push(value);
+                }
+                if let Event::Empty(_) = event {
+                    path.pop();
+                }
+            }
+            Event::Text(e) if path.len() > 1 => {
+                let text = e.unescape().map(|text| text.to_string()).unwrap_or_default();
+                values.entry(path[1..].join("/")).or_default().push(text);
+            }
+            Event::CData(e) if path.len() > 1 => {
+                values.entry(path[1..].join("/")).or_default().push(event_name(e));
+            }
+            Event::End(_) => {
+                path.pop();
+            }
+            _ => (),
+        }
+    }
+    values
+}
+
+/// Converts a split element into a JSON object: child elements become properties (arrays if
+/// repeated), attributes become properties prefixed with `@`, and elements with text only
+/// become strings. Text next to attributes or child elements is stored in the `#text` property.
+fn to_json(events: &[Event]) -> Value {
+    let mut stack: Vec<(String, Map<String, Value>, String)> = Vec::new();
+    let mut root = Value::Null;
+    for event in events {
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let mut object = Map::new();
+                for (name, value) in event_attributes(e) {
+                    object.insert(format!("@{}", name), Value::String(value));
+                }
+                stack.push((event_name(e.name().as_ref()), object, String::new()));
+                if let Event::Empty(_) = event {
+                    close(&mut stack, &mut root);
+                }
+            }
+            Event::Text(e) => {
+                if let Some((_, _, text)) = stack.last_mut() {
+                    text.push_str(&e.unescape().map(|text| text.to_string()).unwrap_or_default());
+                }
+            }
+            Event::CData(e) => {
+                if let Some((_, _, text)) = stack.last_mut() {
+                    text.push_str(&event_name(e));
+                }
+            }
+            Event::End(_) => close(&mut stack, &mut root),
+            _ => (),
+        }
+    }
+    root
+}
+
+fn close(stack: &mut Vec<(String, Map<String, Value>, String)>, root: &mut Value) {
+    if let Some((name, mut object, text)) = stack.pop() {
+        let value = if object.is_empty() {
+            Value::String(text)
+        } else {
+            if !text.trim().is_empty() {
+                object.insert("#text".to_string(), Value::String(text));
+            }
+            Value::Object(object)
+        };
+        match stack.last_mut() {
+            Some((_, parent, _)) => match parent.get_mut(&name) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => {
+                    let first = existing.take();
+                    *existing = Value::Array(vec![first, value]);
+                }
+                None => {
+                    parent.insert(name, value);
+                }
+            },
+            None => *root = value,
+        }
+    }
+}
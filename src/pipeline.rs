@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use chrono::{Local, NaiveDateTime};
+
+use crate::config::Config;
+use crate::output::Sink;
+use crate::common::get_groups;
+use crate::error::Result;
+use crate::transformations::transform::*;
+use crate::transformations::trace::Selection;
+
+/// Filters, splits and transforms XML according to a configuration. The pipeline has no side effects
+/// besides logging and writing to the sinks passed to `run`, so it can process in-memory XML as well as files.
+pub struct Pipeline<'a> {
+    config: &'a Config,
+    rules: Rules,
+    msg_config: &'a HashMap<String, HashMap<String, String>>,
+    selection: Option<Selection>,
+    now: Option<NaiveDateTime>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Creates a pipeline for the given configuration, compiling its filter and transformation rules once
+    /// for all runs (invalid regular expressions and transformation values are rejected); log messages
+    /// are taken from the message configuration (an empty one logs the message keys instead).
+    pub fn new(config: &'a Config, msg_config: &'a HashMap<String, HashMap<String, String>>) -> Result<Self> {
+        let rules = Rules::compile(config, msg_config)?;
+        Ok(Pipeline { config, rules, msg_config, selection: None, now: None })
+    }
+
+    /// Logs the filter, grouping and transformation decisions for the split elements chosen by the selection.
+    pub fn trace(mut self, selection: Selection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Anchors the relative dates of the filter rules (`today`, `now`) to the given time of the run
+    /// instead of the time each run starts.
+    pub fn at(mut self, now: NaiveDateTime) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// The groups the split elements are sorted into, including the residue and the default group.
+    pub fn groups(&self) -> Vec<String> {
+        get_groups(self.config)
+    }
+
+    /// Reads XML from the input and writes the split elements to the sink of their group
+    /// (the split elements of groups without a sink are dropped).
+    pub fn run<R: BufRead, S: Sink>(&self, input: R, sinks: &mut HashMap<String, S>) -> Result<Report> {
+        let now = self.now.unwrap_or_else(|| Local::now().naive_local());
+        transform_traced(input, self.config, &self.rules, self.msg_config, sinks, self.selection.clone(), now)
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::fs::remove_file;
+use std::path::Path;
+use log::{error, info};
+
+use xtract::pipeline::Pipeline;
+use xtract::config::*;
+use crate::utils::*;
+use crate::upload::*;
+use crate::schema::schema_check;
+use xtract::output::*;
+use xtract::compression::open_original;
+use xtract::error::*;
+
+/// The result of processing a single original file.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub original: String,
+    pub uploads_failed: HashMap<String, Vec<Upload>>,
+    pub archiving_failed: Vec<String>,
+    /// The files that don't match their XML schema, with the validity errors.
+    pub validation_failed: Vec<String>,
+    /// The error that ended the processing of the original file, if any,
+    /// and its description for the report (including the consequences).
+    pub error: Option<Error>,
+    pub error_msg: String,
+    /// The errors of failed uploads and archiving, which don't end the processing.
+    pub errors: Vec<Error>,
+}
+
+impl Outcome {
+    pub fn new(original: &str) -> Self {
+        Outcome {
+            original: original.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.error.is_none() && self.uploads_failed.is_empty() && self.archiving_failed.is_empty()
+        && self.validation_failed.is_empty()
+    }
+
+    /// Records the error that ended the processing of the original file.
+    pub fn fail(&mut self, error_msg: String, error: Error) {
+        self.error = Some(error);
+        self.error_msg = error_msg;
+    }
+
+    /// Returns the error determining the exit code: the one that ended the processing, if any,
+    /// otherwise the first failed upload or archiving.
+    pub fn into_error(self) -> Option<Error> {
+        self.error.or(self.errors.into_iter().next())
+    }
+
+    /// Compiles the problems that occurred while processing the original file, if any.
+    pub fn report(&self, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) -> Option<String> {
+        let mut sections: Vec<String> = Vec::new();
+        if self.error.is_some() {
+            sections.push(self.error_msg.clone());
+        }
+        if !self.validation_failed.is_empty() {
+            let msg = get_msg(msg_config, "validation_report", lang);
+            sections.push(msg + ":\n\n" + &self.validation_failed.join("\n"));
+        }
+        if !self.uploads_failed.is_empty() {
+            let mut list = self.uploads_failed
+            .iter().map(|(k, v)| format!("{}: {:?}", k, v)).collect::<Vec<String>>();
+            list.sort();
+            let msg = get_msg(msg_config, "upload_report", lang);
+            sections.push(msg + ":\n\n" + &list.join("\n"));
+        }
+        if !self.archiving_failed.is_empty() {
+            let msg = get_msg(msg_config, "archiving_report", lang);
+            sections.push(msg + ":\n\n" + &self.archiving_failed.join("\n"));
+        }
+        if sections.is_empty() {
+            None
+        } else {
+            Some(sections.join("\n\n"))
+        }
+    }
+}
+
+/// Filters, splits and transforms the given original file, uploads the split files
+/// and moves them to the history, together with the original file if `archive_original` is set.
+pub fn process_original(
+    path_to_original: &Path,
+    archive_original: bool,
+    current_history: &Path,
+    pipeline: &Pipeline,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> Outcome {
+    let lang = &config.settings.lang;
+    let original_file = &path_to_original.file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_else(|| path_to_original.to_string_lossy().to_string());
+    let mut outcome = Outcome::new(original_file);
+
+    let input = match open_original(path_to_original) {
+        Ok(reader) => reader,
+        Err(err) => {
+            let error = Error::Io {
+                msg: get_msg(msg_config, "reading_original_failed", lang),
+                path: original_file.clone(),
+                source: err,
+            };
+            error!("{error}");
+            outcome.fail(error.to_string(), error);
+            return outcome;
+        }
+    };
+
+    let output_paths: HashMap<String, String> = get_output_paths(config, original_file);
+
+    let mut writers = HashMap::new();
+    for (group, file_path_str) in &output_paths {
+        match create_sink(file_path_str, config.outputs.get(group)) {
+            Ok(writer) => {
+                writers.insert(group.clone(), writer);
+            }
+            Err(err) => {
+                // without the file of a group, its split elements would be lost, so the original file isn't processed:
+                drop(writers);
+                for file_path_str in output_paths.values() {
+                    let _ = remove_file(file_path_str);
+                }
+                let error = Error::Io {
+                    msg: get_msg(msg_config, "writing_file_failed", lang),
+                    path: file_path_str.clone(),
+                    source: err,
+                };
+                let addition = format!("{}.", get_msg(msg_config, "process_cancelled", lang));
+                error!("{error}. {addition}");
+                outcome.fail(format!("{error}\n\n{addition}"), error);
+                return outcome;
+            }
+        }
+    }
+
+    match pipeline.run(input, &mut writers) {
+        Ok(_) => {
+            for (group, writer) in writers {
+                let file_path_str = &output_paths[&group];
+                match finish_output(writer, file_path_str, msg_config, config) {
+                    Ok(_) => {
+                        let filename = file_path_str.rsplit("/").next().unwrap_or(file_path_str);
+                        // a file that doesn't match the schema of its group is neither uploaded nor archived:
+                        if let Some(output) = config.outputs.get(&group).filter(|output| !output.schema.is_empty()) {
+                            if let Err(err) = schema_check(file_path_str, &output.schema, msg_config, lang) {
+                                let msg = get_msg(msg_config, "archiving_prevented_by_schema", lang);
+                                error!("{msg}: {file_path_str}");
+                                outcome.validation_failed.push(err.to_string());
+                                outcome.errors.push(err);
+                                continue;
+                            }
+                        }
+                        let (failed, mut errors): (Vec<Upload>, Vec<Error>) = run_uploads(config, msg_config, file_path_str)
+                        .into_iter().unzip();
+                        for err in &errors {
+                            if let Error::Schema { .. } = err {
+                                outcome.validation_failed.push(err.to_string());
+                            }
+                        }
+                        outcome.errors.append(&mut errors);
+                        if failed.is_empty() {
+                            let file_path = Path::new(file_path_str);
+                            let file_history = &current_history.join(filename);
+                            match archive(file_path, file_history, config) {
+                                Ok(_) => {
+                                    let msg = get_msg(msg_config, "archiving_successful", lang);
+                                    info!("{msg}: {filename} ✅");
+                                }
+                                Err(err) => {
+                                    let error = Error::Io {
+                                        msg: get_msg(msg_config, "archiving_failed", lang),
+                                        path: filename.to_string(),
+                                        source: err,
+                                    };
+                                    error!("{error} ❌");
+                                    outcome.archiving_failed.push(filename.to_string());
+                                    outcome.errors.push(error);
+                                }
+                            }
+                        } else {
+                            let msg = get_msg(msg_config, "archiving_prevented", lang);
+                            error!("{msg}: {file_path_str}");
+                            outcome.uploads_failed.insert(filename.to_string(), failed);
+                        }
+                    }
+                    Err(err) => {
+                        let msg = get_msg(msg_config, "archiving_prevented", lang);
+                        error!("{msg}: {file_path_str}");
+                        outcome.archiving_failed.push(file_path_str.to_string());
+                        outcome.errors.push(err);
+                    }
+                }
+            }
+
+            if !archive_original {
+                return outcome;
+            }
+            let original_history = &current_history.join(original_file);
+            match archive(path_to_original, original_history, config) {
+                Ok(_) => {
+                    let msg = get_msg(msg_config, "archiving_successful", lang);
+                    info!("{msg}: {original_file} ✅");
+                }
+                Err(err) => {
+                    let error = Error::Io {
+                        msg: get_msg(msg_config, "archiving_failed", lang),
+                        path: original_file.clone(),
+                        source: err,
+                    };
+                    let addition = get_msg(msg_config, "archiving_original_failed", lang);
+                    error!("{error} ❌. {addition}");
+                    outcome.fail(format!("{error} ❌\n\n{addition}"), error);
+                }
+            }
+        }
+        Err(err) => {
+            drop(writers);
+            for file_path_str in output_paths.values() {
+                let _ = remove_file(file_path_str);
+            }
+            let msg = get_msg(msg_config, "transformation_failed", lang);
+            let error_msg = format!("{msg}: {original_file} - {err}");
+            let addition = format!(
+                "{}. {}.",
+                get_msg(msg_config, "transforming_original_failed", lang),
+                get_msg(msg_config, "process_cancelled", lang)
+            );
+            error!("{error_msg}. {addition}");
+            outcome.fail(error_msg + "\n\n" + &addition, err);
+        }
+    }
+    outcome
+}
+
+/// Sends the reports of all processed original files in a single e-mail. In batch mode,
+/// the report starts with an overview of all files and is logged even if there were no problems.
+pub fn send_report(
+    outcomes: &[Outcome],
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> Result<()> {
+    let lang = &config.settings.lang;
+    let reports: Vec<(&Outcome, Option<String>)> = outcomes
+    .iter()
+    .map(|outcome| (outcome, outcome.report(msg_config, lang)))
+    .collect();
+
+    if config.settings.batch.active {
+        let overview = reports
+        .iter()
+        .map(|(outcome, report)| match report {
+            Some(text) => format!("• {} ❌\n\n{}\n", outcome.original, text),
+            None => format!("• {} ✅", outcome.original),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+        let successful = outcomes.iter().filter(|outcome| outcome.is_successful()).count();
+        let msg = get_msg(msg_config, "batch_report", lang);
+        let batch_report = format!("{msg} ({successful}/{}):\n\n{overview}", outcomes.len());
+        info!("{batch_report}");
+        if successful < outcomes.len() {
+            return send_mail(config, msg_config, batch_report);
+        }
+    } else {
+        let problems: Vec<String> = reports.into_iter().filter_map(|(_, report)| report).collect();
+        if !problems.is_empty() {
+            return send_mail(config, msg_config, problems.join("\n\n"));
+        }
+    }
+    Ok(())
+}
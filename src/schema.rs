@@ -0,0 +1,73 @@
+use log::{info, error};
+use std::{collections::HashMap, io::{self, Read}, path::Path, process::{Command, Stdio}, thread};
+use crate::utils::get_msg;
+use xtract::compression::open_original;
+use xtract::error::Error;
+
+/// Validates the XML file against the XML schema (XSD) with xmllint. The file is decompressed
+/// while it is piped to xmllint, so compressed files are validated as well.
+/// Returns the validity errors reported by xmllint if the file doesn't match the schema.
+pub fn run_validation(filepath: &str, schema: &str) -> Result<(), String> {
+    let mut input = open_original(Path::new(filepath)).map_err(|err| err.to_string())?;
+    let mut child = Command::new("xmllint")
+    .arg("--noout")
+    .arg("--schema")
+    .arg(Path::new(schema))
+    .arg("-")
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|err| format!("xmllint: {err}"))?;
+
+    // stderr is read in parallel, so that xmllint can't block on a full pipe while the file is being piped to it:
+    let stderr = child.stderr.take().map(|mut stderr| thread::spawn(move || {
+        let mut messages = String::new();
+        let _ = stderr.read_to_string(&mut messages);
+        messages
+    }));
+    let copied = match child.stdin.take() {
+        Some(mut stdin) => io::copy(&mut input, &mut stdin).map(|_| ()),
+        None => Ok(()),
+    };
+    let status = child.wait().map_err(|err| format!("xmllint: {err}"))?;
+    let messages = stderr.and_then(|handle| handle.join().ok()).unwrap_or_default();
+
+    match (status.success(), copied) {
+        (true, Ok(_)) => Ok(()),
+        (true, Err(err)) => Err(err.to_string()),
+        (false, _) => {
+            let details: Vec<&str> = messages
+            .lines()
+            .filter(|line| !line.is_empty() && *line != "- fails to validate")
+            .collect();
+            if details.is_empty() {
+                Err(status.to_string())
+            } else {
+                Err(details.join("\n"))
+            }
+        }
+    }
+}
+
+/// Validates the file against the schema and logs the result.
+pub fn schema_check(filepath: &str, schema: &str, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) -> Result<(), Error> {
+    let filename = filepath.rsplit("/").next().unwrap_or(filepath);
+    match run_validation(filepath, schema) {
+        Ok(_) => {
+            let msg = get_msg(msg_config, "schema_validation_successful", lang);
+            info!("{msg}: {filename} ({schema}) ✅");
+            Ok(())
+        }
+        Err(detail) => {
+            let error = Error::Schema {
+                msg: get_msg(msg_config, "schema_validation_failed", lang),
+                file: filename.to_string(),
+                schema: schema.to_string(),
+                detail,
+            };
+            error!("{error} ❌");
+            Err(error)
+        }
+    }
+}
@@ -1,4 +1,7 @@
 pub mod filter;
+pub mod condition;
 pub mod split;
 pub mod transformer;
-pub mod transform;
\ No newline at end of file
+pub mod scope;
+pub mod transform;
+pub mod trace;
\ No newline at end of file
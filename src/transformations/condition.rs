@@ -0,0 +1,654 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use serde::Deserialize;
+use regex::Regex;
+use chrono::{Days, Months, NaiveDate, NaiveDateTime};
+
+/// A boolean expression over the values found at paths inside a split element, e. g.
+/// `a/b/vendor_code = "X" and not (a/b/payment_method in ["Y", "Z"] or all(a/b/line/code) ~ "^FUND")`.
+/// A comparison holds if any, all or none of the values found at its path satisfy it
+/// (by default, if at least one value does).
+#[derive(Debug, Clone)]
+pub enum Condition {
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Compare(Quantifier, String, Test),
+    Exists(String),
+}
+
+/// What has been found inside a split element: the values found at each path, the paths of all elements
+/// and attributes (including those without a value) and the time of the run relative dates are anchored to.
+#[derive(Debug, Clone)]
+pub struct Found<'a> {
+    pub values: HashMap<String, Vec<String>>,
+    pub paths: &'a HashSet<String>,
+    pub now: NaiveDateTime,
+}
+
+/// How many of the values found at a path inside a split element, e. g. the codes of repeated lines,
+/// have to satisfy a test. If the path doesn't occur, `all` and `none` hold, but `any` doesn't
+/// (in the rules; the filter lists ignore such paths instead of calling `holds`).
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantifier {
+    Any,
+    All,
+    None,
+}
+
+impl Quantifier {
+    pub fn holds<'v, I: IntoIterator<Item = &'v String>, F: Fn(&str) -> bool>(self, values: I, test: F) -> bool {
+        let mut values = values.into_iter();
+        match self {
+            Quantifier::Any => values.any(|value| test(value)),
+            Quantifier::All => values.all(|value| test(value)),
+            Quantifier::None => !values.any(|value| test(value)),
+        }
+    }
+
+    fn parse(word: &str) -> Option<Self> {
+        [Quantifier::Any, Quantifier::All, Quantifier::None].into_iter().find(|quantifier| word.eq_ignore_ascii_case(&quantifier.to_string()))
+    }
+}
+
+impl fmt::Display for Quantifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quantifier::Any => write!(f, "any"),
+            Quantifier::All => write!(f, "all"),
+            Quantifier::None => write!(f, "none"),
+        }
+    }
+}
+
+/// The test a value found at the path of a comparison is put to.
+#[derive(Debug, Clone)]
+pub enum Test {
+    Compare(Operator, Literal),
+    Between(Literal, Literal),
+    NotBetween(Literal, Literal),
+    In(Vec<Literal>),
+    NotIn(Vec<Literal>),
+    Matches(Regex),
+    NotMatches(Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A quoted string is compared with the values as a string, an unquoted number numerically and a date
+/// chronologically with the values parsed in the given date format (values that aren't numbers
+/// or dates don't satisfy a numeric or date comparison).
+#[derive(Debug, Clone)]
+pub enum Literal {
+    Text(String),
+    Number(f64),
+    Date(Moment, String),
+}
+
+/// A point in time: a fixed date or the date (`today`, at midnight) or time (`now`) of the run,
+/// optionally shifted by a number of days, weeks, months or years.
+#[derive(Debug, Clone)]
+pub struct Moment {
+    base: Base,
+    shift: i32,
+    unit: Unit,
+}
+
+#[derive(Debug, Clone)]
+enum Base {
+    Fixed(NaiveDateTime),
+    Today,
+    Now,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    Days,
+    Weeks,
+    Months,
+    Years,
+}
+
+impl Moment {
+    fn resolve(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let base = match self.base {
+            Base::Fixed(moment) => moment,
+            Base::Today => now.date().and_hms_opt(0, 0, 0)?,
+            Base::Now => now,
+        };
+        let amount = self.shift.unsigned_abs();
+        let (days, months) = match self.unit {
+            Unit::Days => (Days::new(amount as u64), Months::new(0)),
+            Unit::Weeks => (Days::new(amount as u64 * 7), Months::new(0)),
+            Unit::Months => (Days::new(0), Months::new(amount)),
+            Unit::Years => (Days::new(0), Months::new(amount.checked_mul(12)?)),
+        };
+        if self.shift < 0 {
+            base.checked_sub_days(days)?.checked_sub_months(months)
+        } else {
+            base.checked_add_days(days)?.checked_add_months(months)
+        }
+    }
+}
+
+/// Parses a date, or a date and time, in the given format (see the chrono crate for the specifiers);
+/// a date without time stands for midnight.
+pub fn parse_date(value: &str, format: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    NaiveDateTime::parse_from_str(value, format).ok()
+    .or_else(|| NaiveDate::parse_from_str(value, format).ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+}
+
+impl Literal {
+    fn compare(&self, value: &str, now: NaiveDateTime) -> Option<Ordering> {
+        match self {
+            Literal::Text(text) => Some(value.cmp(text.as_str())),
+            Literal::Number(number) => value.trim().parse::<f64>().ok().and_then(|value| value.partial_cmp(number)),
+            Literal::Date(moment, format) => Some(parse_date(value, format)?.cmp(&moment.resolve(now)?)),
+        }
+    }
+}
+
+impl Test {
+    fn check(&self, value: &str, now: NaiveDateTime) -> bool {
+        let between = |low: &Literal, high: &Literal| {
+            low.compare(value, now).is_some_and(|ordering| ordering != Ordering::Less)
+            && high.compare(value, now).is_some_and(|ordering| ordering != Ordering::Greater)
+        };
+        match self {
+            Test::Compare(operator, literal) => literal.compare(value, now).is_some_and(|ordering| match operator {
+                Operator::Eq => ordering == Ordering::Equal,
+                Operator::Ne => ordering != Ordering::Equal,
+                Operator::Lt => ordering == Ordering::Less,
+                Operator::Le => ordering != Ordering::Greater,
+                Operator::Gt => ordering == Ordering::Greater,
+                Operator::Ge => ordering != Ordering::Less,
+            }),
+            Test::Between(low, high) => between(low, high),
+            Test::NotBetween(low, high) => !between(low, high),
+            Test::In(literals) => literals.iter().any(|literal| literal.compare(value, now) == Some(Ordering::Equal)),
+            Test::NotIn(literals) => literals.iter().all(|literal| literal.compare(value, now) != Some(Ordering::Equal)),
+            Test::Matches(regex) => regex.is_match(value),
+            Test::NotMatches(regex) => !regex.is_match(value),
+        }
+    }
+}
+
+impl Condition {
+    /// Parses an expression, whose dates are written (and compared with values) in the given date format;
+    /// the error describes the problem and its position in the expression.
+    pub fn parse(expression: &str, date_format: &str) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(expression)?, position: 0, date_format: date_format.to_string() };
+        let condition = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(condition),
+            Some((column, token)) => Err(format!("unexpected {} at column {}", token, column)),
+        }
+    }
+
+    /// Evaluates the condition with what has been found inside a split element.
+    pub fn holds(&self, found: &Found) -> bool {
+        match self {
+            Condition::And(left, right) => left.holds(found) && right.holds(found),
+            Condition::Or(left, right) => left.holds(found) || right.holds(found),
+            Condition::Not(condition) => !condition.holds(found),
+            Condition::Compare(quantifier, path, test) => {
+                quantifier.holds(found.values.get(path).into_iter().flatten(), |value| test.check(value, found.now))
+            }
+            Condition::Exists(path) => found.paths.contains(path),
+        }
+    }
+
+    /// The paths the condition refers to.
+    pub fn paths(&self) -> Vec<&String> {
+        match self {
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                let mut paths = left.paths();
+                paths.extend(right.paths());
+                paths
+            }
+            Condition::Not(condition) => condition.paths(),
+            Condition::Compare(_, path, _) | Condition::Exists(path) => vec![path],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Text(text) => write!(f, "{text:?}"),
+            Token::Symbol(symbol) => write!(f, "`{symbol}`"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 12] = ["!=", "<=", ">=", "!~", "=", "<", ">", "~", "(", ")", "[", "]"];
+
+/// Characters of paths (including namespace prefixes and attributes) and unquoted numbers.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "_-./@:+".contains(c)
+}
+
+/// Splits the expression into tokens together with their column (starting at 1).
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == ',' {
+            tokens.push((column, Token::Symbol(",")));
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("unterminated string at column {}", column)),
+                    Some('\\') if chars.get(i + 1).is_some_and(|next| *next == c || *next == '\\') => {
+                        text.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(next) if *next == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(next) => {
+                        text.push(*next);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push((column, Token::Text(text)));
+        } else if is_word_char(c) {
+            let start = i;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push((column, Token::Word(chars[start..i].iter().collect())));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => {
+                    tokens.push((column, Token::Symbol(symbol)));
+                    i += symbol.len();
+                }
+                None => return Err(format!("unexpected `{}` at column {}", c, column)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser; `not` binds tighter than `and`, which binds tighter than `or`.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    date_format: String,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(usize, Token), String> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => Err(format!("expected {} at the end", expected)),
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next(&format!("`{symbol}`"))? {
+            (_, Token::Symbol(found)) if found == symbol => Ok(()),
+            (column, token) => Err(format!("expected `{}` instead of {} at column {}", symbol, token, column)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+        while self.keyword("or") {
+            self.position += 1;
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.not()?;
+        while self.keyword("and") {
+            self.position += 1;
+            condition = Condition::And(Box::new(condition), Box::new(self.not()?));
+        }
+        Ok(condition)
+    }
+
+    fn not(&mut self) -> Result<Condition, String> {
+        if self.keyword("not") {
+            self.position += 1;
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        if self.peek() == Some(&Token::Symbol("(")) {
+            self.position += 1;
+            let condition = self.or()?;
+            self.expect(")")?;
+            return Ok(condition);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Condition, String> {
+        if self.keyword("exists") && self.tokens.get(self.position + 1).is_some_and(|(_, token)| *token == Token::Symbol("(")) {
+            self.position += 2;
+            let path = match self.next("a path")? {
+                (_, Token::Word(word)) if !is_keyword(&word) => word,
+                (column, token) => return Err(format!("expected a path instead of {} at column {}", token, column)),
+            };
+            self.expect(")")?;
+            return Ok(Condition::Exists(path));
+        }
+        let (quantifier, path) = match self.next("a path")? {
+            (_, Token::Word(word)) if self.peek() == Some(&Token::Symbol("(")) && Quantifier::parse(&word).is_some() => {
+                self.position += 1;
+                let path = match self.next("a path")? {
+                    (_, Token::Word(word)) if !is_keyword(&word) => word,
+                    (column, token) => return Err(format!("expected a path instead of {} at column {}", token, column)),
+                };
+                self.expect(")")?;
+                (Quantifier::parse(&word).unwrap_or(Quantifier::Any), path)
+            }
+            (_, Token::Word(word)) if !is_keyword(&word) => (Quantifier::Any, word),
+            (column, token) => return Err(format!("expected a path instead of {} at column {}", token, column)),
+        };
+        let test = match self.next("an operator")? {
+            (_, Token::Symbol("=")) => Test::Compare(Operator::Eq, self.literal()?),
+            (_, Token::Symbol("!=")) => Test::Compare(Operator::Ne, self.literal()?),
+            (_, Token::Symbol("<")) => Test::Compare(Operator::Lt, self.literal()?),
+            (_, Token::Symbol("<=")) => Test::Compare(Operator::Le, self.literal()?),
+            (_, Token::Symbol(">")) => Test::Compare(Operator::Gt, self.literal()?),
+            (_, Token::Symbol(">=")) => Test::Compare(Operator::Ge, self.literal()?),
+            (_, Token::Symbol("~")) => Test::Matches(self.regex()?),
+            (_, Token::Symbol("!~")) => Test::NotMatches(self.regex()?),
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("in") => Test::In(self.list()?),
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("between") => {
+                let (low, high) = self.range()?;
+                Test::Between(low, high)
+            }
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("not") && self.keyword("in") => {
+                self.position += 1;
+                Test::NotIn(self.list()?)
+            }
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("not") && self.keyword("between") => {
+                self.position += 1;
+                let (low, high) = self.range()?;
+                Test::NotBetween(low, high)
+            }
+            (column, token) => return Err(format!("expected an operator instead of {} at column {}", token, column)),
+        };
+        Ok(Condition::Compare(quantifier, path, test))
+    }
+
+    fn literal(&mut self) -> Result<Literal, String> {
+        let base = match self.next("a value")? {
+            (_, Token::Text(text)) => return Ok(Literal::Text(text)),
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("today") => Base::Today,
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("now") => Base::Now,
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("date") => {
+                self.expect("(")?;
+                let base = match self.next("a quoted date")? {
+                    (column, Token::Text(date)) => match parse_date(&date, &self.date_format) {
+                        Some(moment) => Base::Fixed(moment),
+                        None => return Err(format!("date {:?} at column {} doesn't match the date format {:?}", date, column, self.date_format)),
+                    },
+                    (column, token) => return Err(format!("expected a quoted date instead of {} at column {}", token, column)),
+                };
+                self.expect(")")?;
+                base
+            }
+            (column, Token::Word(word)) => return match word.parse::<f64>() {
+                Ok(number) => Ok(Literal::Number(number)),
+                Err(_) => Err(format!("expected a quoted string, a number or a date instead of `{}` at column {}", word, column)),
+            },
+            (column, token) => return Err(format!("expected a value instead of {} at column {}", token, column)),
+        };
+        let (shift, unit) = self.shift()?;
+        Ok(Literal::Date(Moment { base, shift, unit }, self.date_format.clone()))
+    }
+
+    /// Parses the optional shift of a date, e. g. `- 30 days` or `+ 1 year`.
+    fn shift(&mut self) -> Result<(i32, Unit), String> {
+        let (sign, rest) = match self.peek() {
+            Some(Token::Word(word)) if word.starts_with('-') => (-1, word[1..].to_string()),
+            Some(Token::Word(word)) if word.starts_with('+') => (1, word[1..].to_string()),
+            _ => return Ok((0, Unit::Days)),
+        };
+        self.position += 1;
+        let (column, amount) = match rest.is_empty() {
+            true => self.next("a number of days, weeks, months or years")?,
+            false => (self.tokens[self.position - 1].0, Token::Word(rest)),
+        };
+        let amount = match amount {
+            Token::Word(word) if word.parse::<u16>().is_ok() => word.parse::<i32>().unwrap_or_default(),
+            token => return Err(format!("expected a number of days, weeks, months or years instead of {} at column {}", token, column)),
+        };
+        let unit = match self.next("a unit (days, weeks, months or years)")? {
+            (_, Token::Word(word)) => match word.to_lowercase().trim_end_matches('s') {
+                "day" => Unit::Days,
+                "week" => Unit::Weeks,
+                "month" => Unit::Months,
+                "year" => Unit::Years,
+                _ => return Err(format!("expected days, weeks, months or years instead of `{}`", word)),
+            },
+            (column, token) => return Err(format!("expected days, weeks, months or years instead of {} at column {}", token, column)),
+        };
+        Ok((sign * amount, unit))
+    }
+
+    /// Parses the bounds of a range, e. g. `1000 and 5000` or `today - 30 days and today`.
+    fn range(&mut self) -> Result<(Literal, Literal), String> {
+        let low = self.literal()?;
+        match self.next("`and`")? {
+            (_, Token::Word(word)) if word.eq_ignore_ascii_case("and") => (),
+            (column, token) => return Err(format!("expected `and` instead of {} at column {}", token, column)),
+        }
+        Ok((low, self.literal()?))
+    }
+
+    fn list(&mut self) -> Result<Vec<Literal>, String> {
+        self.expect("[")?;
+        let mut literals = vec![self.literal()?];
+        while self.peek() == Some(&Token::Symbol(",")) {
+            self.position += 1;
+            literals.push(self.literal()?);
+        }
+        self.expect("]")?;
+        Ok(literals)
+    }
+
+    fn regex(&mut self) -> Result<Regex, String> {
+        match self.next("a regular expression")? {
+            (column, Token::Text(pattern)) => Regex::new(&pattern).map_err(|err| {
+                format!("invalid regular expression {:?} at column {}: {}", pattern, column, describe_regex_error(&err))
+            }),
+            (column, token) => Err(format!("expected a quoted regular expression instead of {} at column {}", token, column)),
+        }
+    }
+}
+
+/// The last line of a regex error, which describes the error without repeating the pattern.
+pub(crate) fn describe_regex_error(err: &regex::Error) -> String {
+    let description = err.to_string();
+    let last = description.lines().last().unwrap_or_default();
+    last.strip_prefix("error: ").unwrap_or(last).to_string()
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["and", "or", "not", "in", "between"].iter().any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATE_FORMAT: &str = "%Y-%m-%d";
+
+    fn error(expression: &str) -> String {
+        Condition::parse(expression, DATE_FORMAT).expect_err(expression)
+    }
+
+    /// Evaluates an expression with the given values (pairs of path and value) and paths without a value.
+    fn holds(expression: &str, values: &[(&str, &str)], empty: &[&str]) -> bool {
+        let mut found: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, value) in values {
+            found.entry(path.to_string()).or_default().push(value.to_string());
+        }
+        let paths: HashSet<String> = found.keys().cloned().chain(empty.iter().map(|path| path.to_string())).collect();
+        let now = NaiveDate::from_ymd_opt(2026, 10, 18).and_then(|date| date.and_hms_opt(12, 0, 0)).unwrap();
+        Condition::parse(expression, DATE_FORMAT).unwrap().holds(&Found { values: found, paths: &paths, now })
+    }
+
+    #[test]
+    fn parse_errors_name_their_column() {
+        assert_eq!(error(r#"a = "x" b"#), "unexpected `b` at column 9");
+        assert_eq!(error(r#"a = "x"#), "unterminated string at column 5");
+        assert_eq!(error("a # 1"), "unexpected `#` at column 3");
+        assert_eq!(error("and = 1"), "expected a path instead of `and` at column 1");
+        assert_eq!(error("a is 1"), "expected an operator instead of `is` at column 3");
+        assert_eq!(error("a = b"), "expected a quoted string, a number or a date instead of `b` at column 5");
+        assert_eq!(error("a between 1 or 2"), "expected `and` instead of `or` at column 13");
+        assert_eq!(error("(a = 1 or b = 2"), "expected `)` at the end");
+        assert_eq!(error("a in [1, 2"), "expected `]` at the end");
+        assert_eq!(error("a ="), "expected a value at the end");
+        assert_eq!(error("all(a = 1"), "expected `)` instead of `=` at column 7");
+        assert_eq!(
+            error(r#"a = date("18.10.2026")"#),
+            r#"date "18.10.2026" at column 10 doesn't match the date format "%Y-%m-%d""#
+        );
+        assert!(error(r#"a ~ "(""#).starts_with(r#"invalid regular expression "(" at column 5: "#));
+    }
+
+    #[test]
+    fn logical_operators() {
+        for (x, y) in [(false, false), (false, true), (true, false), (true, true)] {
+            let mut values: Vec<(&str, &str)> = Vec::new();
+            if x {
+                values.push(("x", "1"));
+            }
+            if y {
+                values.push(("y", "1"));
+            }
+            assert_eq!(holds("x = 1 and y = 1", &values, &[]), x && y);
+            assert_eq!(holds("x = 1 or y = 1", &values, &[]), x || y);
+            assert_eq!(holds("not x = 1", &values, &[]), !x);
+            // `not` binds tighter than `and`, which binds tighter than `or`:
+            assert_eq!(holds("not x = 1 and y = 1", &values, &[]), !x && y);
+            assert_eq!(holds("x = 1 or y = 1 and not y = 1", &values, &[]), x);
+            assert_eq!(holds("not (x = 1 or y = 1)", &values, &[]), !(x || y));
+        }
+    }
+
+    #[test]
+    fn quantifiers() {
+        let cases: [(&[&str], [bool; 3]); 4] = [
+            (&[], [false, true, true]),
+            (&["A"], [true, true, false]),
+            (&["A", "B"], [true, false, false]),
+            (&["B"], [false, false, true]),
+        ];
+        for (codes, [any, all, none]) in cases {
+            let values: Vec<(&str, &str)> = codes.iter().map(|code| ("line/code", *code)).collect();
+            assert_eq!(holds(r#"line/code = "A""#, &values, &[]), any, "{codes:?}");
+            assert_eq!(holds(r#"any(line/code) = "A""#, &values, &[]), any, "{codes:?}");
+            assert_eq!(holds(r#"all(line/code) = "A""#, &values, &[]), all, "{codes:?}");
+            assert_eq!(holds(r#"none(line/code) = "A""#, &values, &[]), none, "{codes:?}");
+        }
+    }
+
+    #[test]
+    fn absent_paths() {
+        // a comparison with a path that doesn't occur doesn't hold, whatever the operator:
+        assert!(!holds(r#"x = "A""#, &[], &[]));
+        assert!(!holds(r#"x != "A""#, &[], &[]));
+        assert!(!holds(r#"x not in ["A"]"#, &[], &[]));
+        assert!(holds(r#"not x = "A""#, &[], &[]));
+        assert!(holds(r#"not exists(x) or x = "A""#, &[], &[]));
+        // an element without a value exists, but has no value to compare:
+        assert!(holds("exists(x)", &[], &["x"]));
+        assert!(!holds(r#"x = """#, &[], &["x"]));
+    }
+
+    #[test]
+    fn numbers_and_strings() {
+        assert!(holds("p > 5", &[("p", "10")], &[]));
+        assert!(!holds(r#"p > "5""#, &[("p", "10")], &[]));
+        assert!(!holds("p > 5", &[("p", "ten")], &[]));
+        assert!(!holds("p != 5", &[("p", "ten")], &[]));
+        assert!(holds("p in [1, 2.5]", &[("p", "2.50")], &[]));
+        assert!(holds(r#"p ~ "^VEND-" and p !~ "-0$""#, &[("p", "VEND-1")], &[]));
+    }
+
+    #[test]
+    fn relative_dates() {
+        // the run is at noon on 2026-10-18, `today` at midnight:
+        assert!(holds("d = today", &[("d", "2026-10-18")], &[]));
+        assert!(holds("d < now", &[("d", "2026-10-18")], &[]));
+        assert!(holds("d >= today - 30 days", &[("d", "2026-09-18")], &[]));
+        assert!(!holds("d >= today - 30 days", &[("d", "2026-09-17")], &[]));
+        assert!(holds("d = today - 2 weeks", &[("d", "2026-10-04")], &[]));
+        assert!(holds("d = today + 1 month", &[("d", "2026-11-18")], &[]));
+        assert!(holds("d = today - 1 year", &[("d", "2025-10-18")], &[]));
+        // months are added to the calendar date, which is clamped to the end of shorter months:
+        assert!(holds(r#"d = date("2026-03-31") - 1 month"#, &[("d", "2026-02-28")], &[]));
+        assert!(holds(r#"d > date("2026-01-01")"#, &[("d", "2026-01-02")], &[]));
+        // values that aren't dates in the date format never compare:
+        assert!(!holds("d < today", &[("d", "18.10.2026")], &[]));
+        assert!(!holds("d != today", &[("d", "soon")], &[]));
+        assert_eq!(error("d > today - 30"), "expected a unit (days, weeks, months or years) at the end");
+        assert_eq!(error("d > today - 30 fortnights"), "expected days, weeks, months or years instead of `fortnights`");
+    }
+
+    #[test]
+    fn ranges() {
+        // both bounds belong to the range:
+        for (amount, inside) in [("999.99", false), ("1000", true), ("3000", true), ("5000", true), ("5000.01", false)] {
+            assert_eq!(holds("p between 1000 and 5000", &[("p", amount)], &[]), inside, "{amount}");
+            assert_eq!(holds("p not between 1000 and 5000", &[("p", amount)], &[]), !inside, "{amount}");
+        }
+        // a value that can't be compared lies outside every range:
+        assert!(!holds("p between 1000 and 5000", &[("p", "n/a")], &[]));
+        assert!(holds("p not between 1000 and 5000", &[("p", "n/a")], &[]));
+        assert!(!holds("p between 5000 and 1000", &[("p", "3000")], &[]));
+        for (date, inside) in [("2026-09-17", false), ("2026-09-18", true), ("2026-10-18", true), ("2026-10-19", false)] {
+            assert_eq!(holds("d between today - 30 days and today", &[("d", date)], &[]), inside, "{date}");
+        }
+    }
+}
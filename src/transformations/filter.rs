@@ -1,21 +1,328 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use serde::Deserialize;
+use regex::RegexSet;
+use chrono::NaiveDateTime;
+
+use crate::common::get_msg;
+use crate::error::{Error, Result};
+use crate::transformations::condition::{Condition, Found, Quantifier};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Filter {
     pub residue: String,
+    #[serde(default)]
     pub allowlist: Allowlist,
+    #[serde(default)]
     pub blocklist: Blocklist,
+    #[serde(default)]
+    pub presence: Presence,
+    /// Named boolean expressions over the values inside a split element, all of which must hold
+    /// for the split element to pass the filter.
+    #[serde(default)]
+    pub rules: HashMap<String, String>,
+    /// The format of the dates in the rules and of the values they are compared with.
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+pub(crate) fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+/// The values allowed at each path; by default, all values found at a path must be allowed
+/// (see `quantifiers`).
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct Allowlist {
     pub exact: HashMap<String, Vec<String>>,
     pub regex: HashMap<String, Vec<String>>,
+    pub quantifiers: HashMap<String, Quantifier>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// The values blocked at each path; by default, a split element is blocked if any value found
+/// at a path is blocked (see `quantifiers`).
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct Blocklist {
     pub exact: HashMap<String, Vec<String>>,
     pub regex: HashMap<String, Vec<String>>,
-}
\ No newline at end of file
+    pub quantifiers: HashMap<String, Quantifier>,
+}
+
+/// The paths of elements or attributes that must exist (`existing`) or must not exist (`missing`)
+/// inside a split element for it to pass the filter, no matter whether they have a value.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Presence {
+    pub existing: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+/// The kind of a list of the filter: values found in the allowlist pass the filter, values found
+/// in the blocklist violate it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListKind {
+    Allowlist,
+    Blocklist,
+}
+
+impl ListKind {
+    /// The name of the list in the configuration.
+    fn name(self) -> &'static str {
+        match self {
+            ListKind::Allowlist => "allowlist",
+            ListKind::Blocklist => "blocklist",
+        }
+    }
+}
+
+/// A list of the filter with the regular expressions of each path compiled into a `RegexSet`.
+#[derive(Debug, Clone)]
+struct CompiledList {
+    kind: ListKind,
+    exact: HashMap<String, Vec<String>>,
+    regex: HashMap<String, (RegexSet, Vec<String>)>,
+    quantifiers: HashMap<String, Quantifier>,
+    default: Quantifier,
+}
+
+impl CompiledList {
+    fn new(
+        kind: ListKind,
+        exact: &HashMap<String, Vec<String>>,
+        regex: &HashMap<String, Vec<String>>,
+        quantifiers: &HashMap<String, Quantifier>,
+        default: Quantifier,
+        msg_config: &HashMap<String, HashMap<String, String>>,
+        lang: &String
+    ) -> Result<Self> {
+        let mut compiled: HashMap<String, (RegexSet, Vec<String>)> = HashMap::new();
+        for (path, patterns) in regex {
+            let set = RegexSet::new(patterns).map_err(|err| Error::Config {
+                msg: get_msg(msg_config, "invalid_regex", lang),
+                path: format!("filter.{}.regex", kind.name()),
+                detail: format!("{path} - {err}"),
+            })?;
+            compiled.insert(path.clone(), (set, patterns.clone()));
+        }
+        Ok(CompiledList { kind, exact: exact.clone(), regex: compiled, quantifiers: quantifiers.clone(), default })
+    }
+
+    fn find_exact(&self, path: &String, value: &str) -> Option<&String> {
+        self.exact.get(path).and_then(|list| list.iter().find(|i| *i == value))
+    }
+
+    fn find_regex(&self, path: &String, value: &str) -> Option<&String> {
+        let (set, patterns) = self.regex.get(path)?;
+        set.matches(value).iter().next().map(|i| &patterns[i])
+    }
+
+    fn contains(&self, path: &String, value: &str) -> bool {
+        self.find_exact(path, value).is_some() || self.find_regex(path, value).is_some()
+    }
+
+    fn quantifier(&self, path: &String) -> Quantifier {
+        self.quantifiers.get(path).copied().unwrap_or(self.default)
+    }
+
+    /// The paths of the list whose quantifier differs from the default of the list, in alphabetical order.
+    /// Their values are checked together rather than one by one.
+    fn quantified(&self) -> Vec<&String> {
+        let mut paths: Vec<&String> = self.exact.keys()
+        .chain(self.regex.keys())
+        .filter(|path| self.quantifier(path) != self.default)
+        .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Checks the values found at a quantified path and returns whether the quantifier holds,
+    /// or `None` if the path doesn't occur inside the split element.
+    fn check<'v>(&self, path: &String, values: &'v [(String, String)]) -> Option<(Quantifier, Vec<&'v String>, bool)> {
+        let found: Vec<&String> = values.iter().filter(|(p, _)| p == path).map(|(_, value)| value).collect();
+        if found.is_empty() {
+            return None;
+        }
+        let quantifier = self.quantifier(path);
+        let held = quantifier.holds(found.iter().copied(), |value| self.contains(path, value));
+        Some((quantifier, found, held))
+    }
+
+    /// Whether a quantified path is violated: an allowlist entry if its quantifier doesn't hold,
+    /// a blocklist entry if it holds.
+    fn violated(&self, held: bool) -> bool {
+        match self.kind {
+            ListKind::Allowlist => !held,
+            ListKind::Blocklist => held,
+        }
+    }
+}
+
+/// A named rule of the filter together with its expression and the parsed condition.
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    expression: String,
+    condition: Condition,
+}
+
+/// The filter with its regular expressions and rules compiled once, so that the values of the split elements
+/// are only matched against them (see `Filter::compile`).
+#[derive(Debug, Clone)]
+pub struct CompiledFilter {
+    allowlist: CompiledList,
+    blocklist: CompiledList,
+    presence: Presence,
+    rules: Vec<Rule>,
+}
+
+impl Filter {
+    /// Compiles the regular expressions of the allowlist and the blocklist and parses the rules
+    /// (in the order of their names); an invalid pattern or rule is rejected.
+    pub fn compile(&self, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) -> Result<CompiledFilter> {
+        let mut rules: Vec<Rule> = Vec::new();
+        for (name, expression) in &self.rules {
+            let condition = Condition::parse(expression, &self.date_format).map_err(|err| Error::Config {
+                msg: get_msg(msg_config, "invalid_filter_rule", lang),
+                path: format!("filter.rules.{name}"),
+                detail: format!("{expression} - {err}"),
+            })?;
+            rules.push(Rule { name: name.clone(), expression: expression.clone(), condition });
+        }
+        rules.sort_by(|a, b| a.name.cmp(&b.name));
+        let allowlist = &self.allowlist;
+        let blocklist = &self.blocklist;
+        Ok(CompiledFilter {
+            allowlist: CompiledList::new(ListKind::Allowlist, &allowlist.exact, &allowlist.regex, &allowlist.quantifiers, Quantifier::All, msg_config, lang)?,
+            blocklist: CompiledList::new(ListKind::Blocklist, &blocklist.exact, &blocklist.regex, &blocklist.quantifiers, Quantifier::Any, msg_config, lang)?,
+            presence: self.presence.clone(),
+            rules,
+        })
+    }
+}
+
+impl CompiledFilter {
+    /// Whether the filter has any entries or rules at all (otherwise the values of the split elements
+    /// don't need to be collected).
+    pub fn is_empty(&self) -> bool {
+        [&self.allowlist, &self.blocklist].iter().all(|list| list.exact.is_empty() && list.regex.is_empty())
+        && self.presence.existing.is_empty() && self.presence.missing.is_empty()
+        && self.rules.is_empty()
+    }
+
+    /// Checks the presence entries against the paths found inside a split element
+    /// and returns each entry together with whether it holds.
+    fn check_presence<'p>(&'p self, paths: &HashSet<String>) -> Vec<(&'static str, &'p String, bool)> {
+        let existing = self.presence.existing.iter().map(|path| ("existing", path, paths.contains(path)));
+        let missing = self.presence.missing.iter().map(|path| ("missing", path, !paths.contains(path)));
+        existing.chain(missing).collect()
+    }
+
+    /// Checks a value found at the given path, i. e. the text of an element (`a/b/c`)
+    /// or the value of an attribute (`a/b/c/@attr`), against the list entries with the default
+    /// quantifier and returns a description of the violated filter rule, if any.
+    fn violation(&self, path: &String, value: &String) -> Option<String> {
+        let allowlist = &self.allowlist;
+        if (allowlist.exact.contains_key(path) || allowlist.regex.contains_key(path))
+        && allowlist.quantifier(path) == allowlist.default && !allowlist.contains(path, value) {
+            return Some(format!("allowlist: {} = {:?}", path, value));
+        }
+        let blocklist = &self.blocklist;
+        if blocklist.quantifier(path) != blocklist.default {
+            return None;
+        }
+        if blocklist.find_exact(path, value).is_some() {
+            return Some(format!("blocklist.exact: {} = {:?}", path, value));
+        }
+        if let Some(pattern) = blocklist.find_regex(path, value) {
+            return Some(format!("blocklist.regex: {} = {:?} ~ {:?}", path, value, pattern));
+        }
+        None
+    }
+
+    /// Checks the values found inside a split element (in document order) and the paths of its elements
+    /// and attributes against the lists, the presence entries and the rules and returns a description of
+    /// each violated filter entry: the values violating an entry with the default quantifier one by one,
+    /// then the quantified entries, the presence entries and the rules that don't hold.
+    /// Relative dates in the rules are anchored to the given time of the run.
+    pub fn violations(&self, values: &[(String, String)], paths: &HashSet<String>, now: NaiveDateTime) -> Vec<String> {
+        let mut violations: Vec<String> = values.iter().filter_map(|(path, value)| self.violation(path, value)).collect();
+        for list in [&self.allowlist, &self.blocklist] {
+            for path in list.quantified() {
+                if let Some((quantifier, found, held)) = list.check(path, values) {
+                    if list.violated(held) {
+                        violations.push(format!("{} ({}): {} = {:?}", list.kind.name(), quantifier, path, found));
+                    }
+                }
+            }
+        }
+        violations.extend(self.check_presence(paths).into_iter()
+        .filter(|(_, _, held)| !held)
+        .map(|(kind, path, _)| format!("presence.{}: {}", kind, path)));
+        let found = Found { values: by_path(values), paths, now };
+        violations.extend(self.rules.iter()
+        .filter(|rule| !rule.condition.holds(&found))
+        .map(|rule| format!("rules.{}: {}", rule.name, rule.expression)));
+        violations
+    }
+
+    /// Describes every filter entry the values found inside a split element are checked against and whether
+    /// it matches, and whether each quantified entry, each presence entry and each rule holds
+    /// (used to trace the filter decisions).
+    pub fn explain(&self, values: &[(String, String)], paths: &HashSet<String>, now: NaiveDateTime) -> Vec<String> {
+        let mut decisions: Vec<String> = Vec::new();
+        for (path, value) in values {
+            for list in [&self.allowlist, &self.blocklist] {
+                for entry in list.exact.get(path).into_iter().flatten() {
+                    let result = if entry == value { "matched" } else { "failed" };
+                    decisions.push(format!("{}.exact: {} = {:?} ~ {:?} ➔ {}", list.kind.name(), path, value, entry, result));
+                }
+                if let Some((set, patterns)) = list.regex.get(path) {
+                    let matches = set.matches(value);
+                    for (i, entry) in patterns.iter().enumerate() {
+                        let result = if matches.matched(i) { "matched" } else { "failed" };
+                        decisions.push(format!("{}.regex: {} = {:?} ~ {:?} ➔ {}", list.kind.name(), path, value, entry, result));
+                    }
+                }
+            }
+        }
+        for list in [&self.allowlist, &self.blocklist] {
+            for path in list.quantified() {
+                if let Some((quantifier, found, held)) = list.check(path, values) {
+                    let result = if held { "matched" } else { "failed" };
+                    decisions.push(format!("{} ({}): {} = {:?} ➔ {}", list.kind.name(), quantifier, path, found, result));
+                }
+            }
+        }
+        for (kind, path, held) in self.check_presence(paths) {
+            let result = if held { "matched" } else { "failed" };
+            decisions.push(format!("presence.{}: {} ➔ {}", kind, path, result));
+        }
+        let found = Found { values: by_path(values), paths, now };
+        for rule in &self.rules {
+            let result = if rule.condition.holds(&found) { "matched" } else { "failed" };
+            decisions.push(format!("rules.{}: {} ➔ {}", rule.name, rule.expression, result));
+        }
+        decisions
+    }
+}
+
+fn by_path(values: &[(String, String)]) -> HashMap<String, Vec<String>> {
+    let mut by_path: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, value) in values {
+        by_path.entry(path.clone()).or_default().push(value.clone());
+    }
+    by_path
+}
+
+/// Splits a filter or grouping key of the form `a/b/c/@attr` into the element path
+/// and the attribute name. Keys without attribute part yield `None`.
+pub fn attribute_key(key: &str) -> Option<(&str, &str)> {
+    match key.rsplit_once("/@") {
+        Some((element_path, attribute)) if !attribute.is_empty() && !attribute.contains('/') => {
+            Some((element_path, attribute))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+/// An index of the elements, attributes and text values of a buffered split element. The datafields
+/// and preconditions of a transformation rule are looked up in the scope of each target: the instance
+/// of the deepest element that the path of the target and the looked-up path have in common. For the
+/// target `invoice/lines/line/price`, e. g., `invoice/lines/line/quantity` is looked up in the same `line`,
+/// whereas `invoice/vat_info/vat_percentage` is looked up in the whole `invoice`.
+#[derive(Debug, Default)]
+pub struct Index {
+    /// The number of steps of the split path.
+    level: usize,
+    /// The parent and the depth (the split element being at depth 0) of each element instance.
+    elements: Vec<(Option<usize>, usize)>,
+    /// The element instances open at the current event.
+    open: Vec<usize>,
+    /// The values found at each path: the position of the event, the element instance and the value.
+    values: HashMap<String, Vec<(usize, usize, String)>>,
+    /// The element instances in which each path of an element or attribute exists.
+    existing: HashMap<String, Vec<usize>>,
+    /// The paths of the elements read outside the split elements so far.
+    outside: HashSet<String>,
+}
+
+impl Index {
+    /// Creates an empty index for the split elements at the given path.
+    pub fn new(element: &str) -> Self {
+        Index { level: element.split('/').count(), ..Default::default() }
+    }
+
+    /// Adds an element and its attributes (triples of key, name and value) read at the given position
+    /// and returns its instance.
+    pub fn open(&mut self, path: &str, attributes: &[(String, String, String)], position: usize) -> usize {
+        let instance = self.elements.len();
+        self.elements.push((self.open.last().copied(), self.open.len()));
+        self.open.push(instance);
+        self.existing.entry(path.to_string()).or_default().push(instance);
+        for (key, _name, value) in attributes {
+            self.existing.entry(key.clone()).or_default().push(instance);
+            self.values.entry(key.clone()).or_default().push((position, instance, value.clone()));
+        }
+        instance
+    }
+
+    /// Closes the innermost open element and returns its instance.
+    pub fn close(&mut self) -> usize {
+        self.open.pop().unwrap_or_default()
+    }
+
+    /// Adds the text of the innermost open element read at the given position and returns its instance.
+    pub fn text(&mut self, path: &str, text: &str, position: usize) -> usize {
+        let instance = self.current();
+        self.values.entry(path.to_string()).or_default().push((position, instance, text.to_string()));
+        instance
+    }
+
+    /// The innermost open element instance.
+    pub fn current(&self) -> usize {
+        self.open.last().copied().unwrap_or_default()
+    }
+
+    /// Notes an element read outside the split elements.
+    pub fn outside(&mut self, path: &str) {
+        self.outside.insert(path.to_string());
+    }
+
+    /// Forgets the split element, keeping the elements read outside the split elements.
+    pub fn clear(&mut self) {
+        self.elements.clear();
+        self.open.clear();
+        self.values.clear();
+        self.existing.clear();
+    }
+
+    /// Looks up the value at the given path in the scope of a target (given by its path and the element
+    /// instance it belongs to): the most recent value before the position of the target or, if there is
+    /// none, the first value after it. Paths outside the split element have no values.
+    pub fn value(&self, path: &str, target: &str, position: usize, instance: usize) -> Option<&String> {
+        let (depth, scope) = self.scope(path, target, instance)?;
+        let mut following: Option<&String> = None;
+        let mut preceding: Option<&String> = None;
+        for (found, element, value) in self.values.get(path)? {
+            if self.ancestor(*element, depth) != scope {
+                continue;
+            }
+            if *found <= position {
+                preceding = Some(value);
+            } else if following.is_none() {
+                following = Some(value);
+            }
+        }
+        preceding.or(following)
+    }
+
+    /// Checks whether an element or attribute exists in the scope of a target (given by its path and
+    /// the element instance it belongs to). Paths outside the split element exist if they were read before.
+    pub fn exists(&self, path: &str, target: &str, instance: usize) -> bool {
+        match self.scope(path, target, instance) {
+            Some((depth, scope)) => self.existing.get(path)
+            .is_some_and(|elements| elements.iter().any(|element| self.ancestor(*element, depth) == scope)),
+            None => self.outside.contains(path),
+        }
+    }
+
+    /// The depth and the instance of the deepest element that the path and the target have in common,
+    /// if it is inside the split element.
+    fn scope(&self, path: &str, target: &str, instance: usize) -> Option<(usize, usize)> {
+        let common = element_steps(path).zip(element_steps(target)).take_while(|(a, b)| a == b).count();
+        let depth = common.checked_sub(self.level)?;
+        Some((depth, self.ancestor(instance, depth)))
+    }
+
+    /// The ancestor of an element instance at the given depth.
+    fn ancestor(&self, mut instance: usize, depth: usize) -> usize {
+        while let Some((Some(parent), level)) = self.elements.get(instance) {
+            if *level <= depth {
+                break;
+            }
+            instance = *parent;
+        }
+        instance
+    }
+}
+
+/// The names of the elements of a path, without a trailing attribute.
+fn element_steps(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|step| !step.starts_with('@'))
+}
@@ -1,9 +1,217 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use serde::Deserialize;
 
+/// Which of the values found at a grouping key within one split element determine its groups:
+/// the first or the last one read, or all of them (fan-out to several groups).
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Matches {
+    First,
+    #[default]
+    Last,
+    All,
+}
+
+impl fmt::Display for Matches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Matches::First => "first",
+            Matches::Last => "last",
+            Matches::All => "all",
+        })
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Split {
+    #[serde(default)]
     pub declaration: bool,
     pub default: String,
+    #[serde(default)]
     pub grouping: HashMap<String, HashMap<String, String>>,
-}
\ No newline at end of file
+    /// Which matches of a grouping key count (default: the last one read).
+    #[serde(default)]
+    pub matches: HashMap<String, Matches>,
+    /// Fallback labels for split elements in which the given path doesn't exist.
+    #[serde(default)]
+    pub missing: HashMap<String, String>,
+    /// Fallback labels for split elements in which the given path exists.
+    #[serde(default)]
+    pub existing: HashMap<String, String>,
+}
+
+impl Split {
+    /// Looks up the label for a value found at the given path, i. e. the text of an element (`a/b/c`)
+    /// or the value of an attribute (`a/b/c/@attr`). If the path is a grouping key but the value
+    /// has no label, the default label is returned; if the path is no grouping key, `None` is returned.
+    /// The label comes together with the grouping key.
+    pub fn get_group(&self, path: &String, value: &String) -> Option<(&String, &String)> {
+        self.grouping
+        .get_key_value(path)
+        .map(|(key, labeling)| (key, labeling.get(value).unwrap_or(&self.default)))
+    }
+
+    /// Returns which matches of the given grouping key count.
+    pub fn matches(&self, path: &String) -> Matches {
+        self.matches.get(path).copied().unwrap_or_default()
+    }
+
+    /// Determines the groups of a split element from the labels found at its grouping keys, given as
+    /// (path, label) in the order they were read. Among the keys matching `first` or `last`, the selected
+    /// match read last determines one label, as before; the keys matching `all` add the labels of all
+    /// their values except the default label. Without any label, the element goes to the default group.
+    pub fn select_groups<'s>(&'s self, found: &[(&'s String, &'s String)]) -> Vec<&'s String> {
+        let mut single: Option<&String> = None;
+        let mut multiple: Vec<&String> = Vec::new();
+        let mut seen: HashSet<&String> = HashSet::new();
+        for (path, label) in found {
+            let first = seen.insert(*path);
+            match self.matches(path) {
+                Matches::First if first => single = Some(*label),
+                Matches::First => (),
+                Matches::Last => single = Some(*label),
+                Matches::All => {
+                    if *label != &self.default && !multiple.contains(label) {
+                        multiple.push(*label);
+                    }
+                }
+            }
+        }
+        let mut groups: Vec<&String> = single
+        .filter(|label| *label != &self.default || multiple.is_empty())
+        .into_iter()
+        .collect();
+        groups.extend(multiple.into_iter().filter(|label| Some(*label) != single));
+        if groups.is_empty() {
+            groups.push(&self.default);
+        }
+        groups
+    }
+
+    /// Returns the different labels (except the default label) found at the grouping keys
+    /// of a split element, if there is more than one, i. e. if the grouping is ambiguous.
+    pub fn conflicts<'s>(&self, found: &[(&'s String, &'s String)]) -> Vec<&'s String> {
+        let mut labels: Vec<&String> = Vec::new();
+        for (_, label) in found {
+            if *label != &self.default && !labels.contains(label) {
+                labels.push(*label);
+            }
+        }
+        if labels.len() > 1 { labels } else { Vec::new() }
+    }
+
+    /// Looks up the fallback label for a split element that hasn't got a label from the grouping,
+    /// given the paths of all elements and attributes found inside it. The `missing` entries are checked
+    /// before the `existing` ones, each in the alphabetical order of their paths; the first match wins.
+    pub fn get_fallback(&self, paths: &HashSet<String>) -> Option<(&'static str, &String, &String)> {
+        let mut missing: Vec<(&String, &String)> = self.missing.iter().filter(|(path, _)| !paths.contains(*path)).collect();
+        let mut existing: Vec<(&String, &String)> = self.existing.iter().filter(|(path, _)| paths.contains(*path)).collect();
+        missing.sort();
+        existing.sort();
+        missing.first().map(|(path, label)| ("missing", *path, *label))
+        .or_else(|| existing.first().map(|(path, label)| ("existing", *path, *label)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A split with the grouping keys `a` (1 ➔ A, 2 ➔ B) and `b` (1 ➔ C, 2 ➔ D, 3 ➔ A),
+    /// matching as given.
+    fn split(matches: &[(&str, Matches)]) -> Split {
+        let labeling = |labels: &[(&str, &str)]| labels.iter()
+        .map(|(value, label)| (value.to_string(), label.to_string()))
+        .collect::<HashMap<String, String>>();
+        Split {
+            declaration: false,
+            default: "DEFAULT".to_string(),
+            grouping: HashMap::from([
+                ("a".to_string(), labeling(&[("1", "A"), ("2", "B")])),
+                ("b".to_string(), labeling(&[("1", "C"), ("2", "D"), ("3", "A")])),
+            ]),
+            matches: matches.iter().map(|(path, matches)| (path.to_string(), *matches)).collect(),
+            missing: HashMap::new(),
+            existing: HashMap::new(),
+        }
+    }
+
+    /// The labels of the values found in a split element (pairs of path and value, in the order they were read).
+    fn found<'s>(split: &'s Split, values: &[(&str, &str)]) -> Vec<(&'s String, &'s String)> {
+        values.iter()
+        .filter_map(|(path, value)| split.get_group(&path.to_string(), &value.to_string()))
+        .collect()
+    }
+
+    fn groups(split: &Split, values: &[(&str, &str)]) -> Vec<String> {
+        split.select_groups(&found(split, values)).into_iter().cloned().collect()
+    }
+
+    fn conflicts(split: &Split, values: &[(&str, &str)]) -> Vec<String> {
+        split.conflicts(&found(split, values)).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn last_match_counts_by_default() {
+        let split = split(&[]);
+        assert_eq!(groups(&split, &[]), ["DEFAULT"]);
+        assert_eq!(groups(&split, &[("a", "9")]), ["DEFAULT"]);
+        assert_eq!(groups(&split, &[("a", "1"), ("a", "2")]), ["B"]);
+        assert_eq!(groups(&split, &[("a", "1"), ("b", "2")]), ["D"]);
+        // a value without label counts as well:
+        assert_eq!(groups(&split, &[("a", "1"), ("a", "9")]), ["DEFAULT"]);
+        assert!(split.get_group(&"c".to_string(), &"1".to_string()).is_none());
+    }
+
+    #[test]
+    fn first_match_counts() {
+        let split = split(&[("a", Matches::First)]);
+        assert_eq!(groups(&split, &[("a", "1"), ("a", "2")]), ["A"]);
+        // the selected match of each key read last decides:
+        assert_eq!(groups(&split, &[("a", "1"), ("b", "2"), ("a", "2")]), ["D"]);
+        assert_eq!(groups(&split, &[("b", "2"), ("a", "1"), ("a", "2")]), ["A"]);
+    }
+
+    #[test]
+    fn all_matches_fan_out() {
+        let split = split(&[("a", Matches::All)]);
+        assert_eq!(groups(&split, &[("a", "1"), ("a", "2"), ("a", "1")]), ["A", "B"]);
+        assert_eq!(groups(&split, &[("a", "2"), ("a", "1")]), ["B", "A"]);
+        // values without label don't add the default group, unless there is no other label:
+        assert_eq!(groups(&split, &[("a", "9"), ("a", "1")]), ["A"]);
+        assert_eq!(groups(&split, &[("a", "9")]), ["DEFAULT"]);
+        // the label of the keys matching `first` or `last` comes first, without duplicates:
+        assert_eq!(groups(&split, &[("a", "2"), ("b", "1"), ("a", "1")]), ["C", "B", "A"]);
+        assert_eq!(groups(&split, &[("b", "3"), ("a", "1"), ("a", "2")]), ["A", "B"]);
+        assert_eq!(groups(&split, &[("b", "9"), ("a", "1")]), ["A"]);
+    }
+
+    #[test]
+    fn different_labels_conflict() {
+        for matches in [Matches::First, Matches::Last, Matches::All] {
+            let split = split(&[("a", matches)]);
+            assert_eq!(conflicts(&split, &[("a", "1"), ("b", "2")]), ["A", "D"], "{matches}");
+            assert_eq!(conflicts(&split, &[("a", "1"), ("a", "2"), ("a", "1")]), ["A", "B"], "{matches}");
+            assert!(conflicts(&split, &[("a", "1"), ("b", "3")]).is_empty(), "{matches}");
+            assert!(conflicts(&split, &[("a", "9"), ("a", "1")]).is_empty(), "{matches}");
+        }
+    }
+
+    #[test]
+    fn fallbacks() {
+        let mut split = split(&[]);
+        split.missing = HashMap::from([("x/y".to_string(), "M2".to_string()), ("x/a".to_string(), "M1".to_string())]);
+        split.existing = HashMap::from([("x/e".to_string(), "E".to_string())]);
+        let fallback = |paths: &[&str]| {
+            let paths: HashSet<String> = paths.iter().map(|path| path.to_string()).collect();
+            split.get_fallback(&paths).map(|(kind, path, label)| (kind, path.clone(), label.clone()))
+        };
+        let expected = |kind: &'static str, path: &str, label: &str| Some((kind, path.to_string(), label.to_string()));
+        assert_eq!(fallback(&[]), expected("missing", "x/a", "M1"));
+        assert_eq!(fallback(&["x/a"]), expected("missing", "x/y", "M2"));
+        assert_eq!(fallback(&["x/e"]), expected("missing", "x/a", "M1"));
+        assert_eq!(fallback(&["x/a", "x/y", "x/e"]), expected("existing", "x/e", "E"));
+        assert_eq!(fallback(&["x/a", "x/y"]), None);
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use log::info;
+
+use crate::common::get_msg;
+use crate::config::Config;
+
+/// Selects the split elements to be traced, either by their index in the original file
+/// (starting at 1) or by a path (`a/b/c` or `a/b/c/@attr`) and the value found there.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    pub indices: Vec<usize>,
+    pub key: Option<(String, String)>,
+}
+
+/// Collects the filter, grouping and transformation decisions for the current split element,
+/// which are logged at its end tag if the element is selected. Since a split element can be
+/// selected by a value that occurs anywhere inside it, the decisions have to be buffered.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub selection: Option<Selection>,
+    pub lines: Vec<String>,
+    pub selected: bool,
+}
+
+impl Trace {
+    pub fn new(selection: Option<Selection>) -> Self {
+        Trace {
+            selection,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.selection.is_some()
+    }
+
+    /// Resets the trace at the start tag of a split element.
+    pub fn start(&mut self, index: usize) {
+        self.lines.clear();
+        self.selected = self.selection.as_ref().is_some_and(|selection| selection.indices.contains(&index));
+    }
+
+    /// Checks whether the value found at the given path selects the current split element.
+    pub fn check_key(&mut self, path: &String, value: &String) {
+        if let Some((key_path, key_value)) = self.selection.as_ref().and_then(|selection| selection.key.as_ref()) {
+            if key_path == path && key_value == value {
+                self.selected = true;
+            }
+        }
+    }
+
+    /// Adds a line to the trace; the line is only built if tracing is active.
+    pub fn log<F: FnOnce() -> String>(&mut self, line: F) {
+        if self.is_active() {
+            self.lines.push(line());
+        }
+    }
+
+    /// Logs the collected decisions at the end tag of a split element, if it is selected.
+    pub fn finish(
+        &mut self,
+        index: usize,
+        group: &str,
+        config: &Config,
+        msg_config: &HashMap<String, HashMap<String, String>>
+    ) {
+        if self.selected {
+            let msg = get_msg(msg_config, "trace_split_element", &config.settings.lang);
+            info!("{} #{} ➔ {}:\n  • {}", msg, index, group, self.lines.join("\n  • "));
+        }
+        self.lines.clear();
+        self.selected = false;
+    }
+}
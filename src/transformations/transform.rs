@@ -1,249 +1,832 @@
-use std::fs;
-use std::io::Result;
-use std::collections::HashMap;
-use std::path::Path;
-use log::{error, info};
-use quick_xml::events::{Event, BytesText};
-use quick_xml::reader::Reader;
-use regex::Regex;
+use std::io::BufRead;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use log::info;
+use chrono::{Local, NaiveDateTime};
+use encoding_rs::{Encoding, UTF_8};
+use quick_xml::events::{Event, BytesCData, BytesDecl, BytesEnd, BytesPI, BytesStart, BytesText};
+use quick_xml::name::{PrefixDeclaration, QName, ResolveResult};
+use quick_xml::reader::NsReader;
+use crate::transformations::filter::{attribute_key, CompiledFilter};
+use crate::transformations::scope::Index;
 use crate::transformations::transformer::*;
-use crate::utils::*;
+use crate::transformations::trace::*;
+use crate::common::*;
 use crate::config::*;
+use crate::output::Sink;
+use crate::error::{Error, Result};
 
-pub fn transform<'a>(
-    file_path: &Path,
-    config: &Config,
-    msg_config: &HashMap<String, HashMap<String, String>>
-) -> Result<HashMap<String, Vec<Event<'a>>>> {
-    let mut reader: Reader<std::io::BufReader<fs::File>> = Reader::from_file(file_path).unwrap();
-    let mut buf: Vec<u8> = Vec::new();
-    let mut split_element: Vec<Event<'_>> = Vec::new();
-    let lang = &config.settings.lang;
+/// Summary of a transformation run: the number of split elements written to each group,
+/// the filter rules that sent split elements to the residue (by their index in the original file,
+/// starting at 1), the split elements whose grouping keys matched different labels
+/// and how often each transformation was applied (in configuration order).
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub elements: HashMap<String, usize>,
+    pub residue: Vec<(usize, Vec<String>)>,
+    pub conflicts: Vec<(usize, Vec<String>)>,
+    pub transformations: Vec<(String, usize)>,
+}
 
-    let mut transformers: Vec<Transformer> = config.transformations.clone().into_iter()
-    .map(|transformation| Transformer::new(transformation)).collect::<Vec<Transformer>>();
+/// The filter and transformation rules of a configuration in compiled form: the regular expressions
+/// of the filter and the expression trees of the transformation values are built only once
+/// and shared by all runs.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    pub filter: CompiledFilter,
+    pub transformers: Vec<Transformer>,
+}
 
-    for t in &mut transformers {
-        t.initialize_preconditions();
+impl Rules {
+    /// Compiles the rules of the configuration; invalid regular expressions and transformation values are rejected.
+    pub fn compile(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Result<Self> {
+        let lang = &config.settings.lang;
+        let transformers = config.transformations.iter()
+        .enumerate()
+        .map(|(i, transformation)| Transformer::new(i + 1, transformation.clone()).compile(msg_config, lang))
+        .collect::<Result<Vec<Transformer>>>()?;
+        Ok(Rules { filter: config.filter.compile(msg_config, lang)?, transformers })
     }
+}
+
+/// An event below the split level, buffered together with the names resolved while reading it:
+/// the path of the element (for empty elements including the element itself), its attributes,
+/// the unescaped text of a text event, the element instance in the index of the split element
+/// and the namespace prefixes in scope.
+struct Buffered {
+    event: Event<'static>,
+    path: String,
+    attributes: Vec<(String, String, String)>,
+    text: String,
+    bindings: Rc<Bindings>,
+    instance: usize,
+}
 
-    let mut groups = config.split.grouping
-    .values().cloned().collect::<Vec<HashMap<String, String>>>()
-    .into_iter().map(|h| h.values().cloned().collect::<Vec<String>>())
-    .into_iter().flatten().collect::<Vec<String>>();
-    groups.sort_unstable();
-    groups.dedup();
+/// The named namespace prefixes declared by an element and its ancestors as pairs of prefix
+/// and namespace URI, the innermost declarations last.
+type Bindings = Vec<(String, String)>;
 
-    groups.push(config.filter.residue.clone());
-    groups.push(config.split.default.clone());
+pub fn transform<R: BufRead, S: Sink>(
+    input: R,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    writers: &mut HashMap<String, S>
+) -> Result<Report> {
+    let rules = Rules::compile(config, msg_config)?;
+    transform_traced(input, config, &rules, msg_config, writers, None, Local::now().naive_local())
+}
 
-    let mut current_group: &String = &config.split.default;
+/// Works like `transform`, but additionally logs the filter, grouping and transformation
+/// decisions for the split elements chosen by the given selection. Relative dates in the
+/// filter rules are anchored to the given time of the run.
+pub fn transform_traced<R: BufRead, S: Sink>(
+    input: R,
+    config: &Config,
+    rules: &Rules,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    writers: &mut HashMap<String, S>,
+    selection: Option<Selection>,
+    now: NaiveDateTime
+) -> Result<Report> {
+    let mut reader = NsReader::from_reader(input);
+    let mut buf: Vec<u8> = Vec::new();
+    let lang = &config.settings.lang;
+
+    // Namespace URIs are mapped to the prefixes used in the configuration, so that
+    // element and attribute names can be matched regardless of the prefixes in the document:
+    let prefixes: HashMap<&String, &String> = config.namespaces.iter().map(|(prefix, uri)| (uri, prefix)).collect();
     let mut current_path: Vec<String> = Vec::new();
-    let mut splitting: HashMap<String, Vec<Event<'_>>> = groups.into_iter().map(|g| (g, Vec::new())).collect(); 
+    let mut bindings: Vec<Rc<Bindings>> = Vec::new();
     let split_path: Vec<&str> = config.element.split("/").collect();
-    let mut include: bool = true;
-    let mut keep: bool = true;
-    
+    let mut splitter = Splitter::new(config, rules, msg_config, selection, now);
+
+    // Everything below the split level is buffered and indexed up to the end tag of the split element,
+    // so that the transformations can use values found anywhere inside the split element:
+    let mut buffered: Vec<Buffered> = Vec::new();
+    let mut index = Index::new(&config.element);
+
     loop {
-        match reader.read_event_into(&mut buf) {
+        let event = reader.read_event_into(&mut buf);
+        // The encoding is known after the XML declaration has been read:
+        let encoding = reader.decoder().encoding();
+        match event {
             Ok(Event::Decl(e)) => {
                 if config.split.declaration {
-                    for xml_event_list in splitting.values_mut() {
-                        xml_event_list.push(Event::Decl(e.clone().into_owned()));
+                    for (group, writer) in writers.iter_mut() {
+                        write_events(writer, group, &[to_utf8(Event::Decl(e.borrow()), encoding)], msg_config, lang)?;
                     }
                 }
             }
 
             Ok(Event::Start(e)) => {
-                current_path.push(String::from_utf8(e.name().as_ref().to_owned()).unwrap());
+                current_path.push(element_name(&reader, e.name(), &prefixes));
+                bindings.push(bind_prefixes(&e, bindings.last(), encoding));
                 let current_path_string = current_path.join("/");
-                for t in &mut transformers {
-                    if let Some(elements) = t.transformation.preconditions.get("missing") {
-                        if elements.contains(&current_path_string) {
-                            *t.missing.entry(current_path_string.clone()).or_default() = false;
-                        }
-                    }
-                    if let Some(elements) = t.transformation.preconditions.get("existing") {
-                        if elements.contains(&current_path_string) {
-                            *t.existing.entry(current_path_string.clone()).or_default() = true;
-                        }
-                    }
-                    if t.transformation.target == current_path_string {
-                        if !t.transformation.keep {
-                            keep = false;
-                        }
+                if superordinate(&current_path, &split_path) {
+                    index.outside(&current_path_string);
+                    for (group, writer) in writers.iter_mut() {
+                        write_events(writer, group, &[to_utf8(Event::Start(e.borrow()), encoding)], msg_config, lang)?;
                     }
+                } else {
+                    let attributes = get_attributes(&e, &current_path_string, &reader, &prefixes, config, msg_config)?;
+                    let instance = index.open(&current_path_string, &attributes, buffered.len());
+                    buffered.push(Buffered {
+                        event: to_utf8(Event::Start(e), encoding).into_owned(),
+                        path: current_path_string,
+                        attributes,
+                        text: String::new(),
+                        bindings: bindings.last().cloned().unwrap_or_default(),
+                        instance,
+                    });
                 }
-                if !keep {
-                    continue;
-                }
-                if superordinate(&current_path, &split_path).unwrap() {
-                    for xml_event_list in splitting.values_mut() {
-                        xml_event_list.push(Event::Start(e.clone().into_owned()));
+            }
+
+            Ok(Event::Empty(e)) => {
+                current_path.push(element_name(&reader, e.name(), &prefixes));
+                let element_path = current_path.join("/");
+                if superordinate(&current_path, &split_path) {
+                    index.outside(&element_path);
+                    for (group, writer) in writers.iter_mut() {
+                        write_events(writer, group, &[to_utf8(Event::Empty(e.borrow()), encoding)], msg_config, lang)?;
                     }
                 } else {
-                    split_element.push(Event::Start(e.clone().into_owned()));
+                    let attributes = get_attributes(&e, &element_path, &reader, &prefixes, config, msg_config)?;
+                    let prefixes_in_scope = bind_prefixes(&e, bindings.last(), encoding);
+                    let instance = index.open(&element_path, &attributes, buffered.len());
+                    index.close();
+                    buffered.push(Buffered {
+                        event: to_utf8(Event::Empty(e), encoding).into_owned(),
+                        path: element_path,
+                        attributes,
+                        text: String::new(),
+                        bindings: prefixes_in_scope,
+                        instance,
+                    });
+                    // a self-closing split element is filtered and grouped like any other:
                     if current_path == split_path {
-                        include = true;
+                        splitter.replay(&mut buffered, &index, writers)?;
+                        index.clear();
                     }
                 }
+                current_path.pop();
             }
 
             Ok(Event::Text(e)) => {
                 if e.starts_with("\n".as_bytes()) || e.starts_with("\r".as_bytes()) {
                     continue;
                 }
-                if !keep {
-                    continue;
+                if superordinate(&current_path, &split_path) {
+                    for (group, writer) in writers.iter_mut() {
+                        write_events(writer, group, &[to_utf8(Event::Text(e.borrow()), encoding)], msg_config, lang)?;
+                    }
+                } else {
+                    let current_path_string = current_path.join("/");
+                    let text_from_event = e.unescape().map_err(|err| Error::Xml {
+                        msg: get_msg(msg_config, "reading_xml_event_failed", lang),
+                        position: reader.buffer_position(),
+                        detail: format!("{current_path_string} - {err}"),
+                    })?.to_string();
+                    let instance = index.text(&current_path_string, &text_from_event, buffered.len());
+                    buffered.push(Buffered {
+                        event: to_utf8(Event::Text(e), encoding).into_owned(),
+                        path: current_path_string,
+                        attributes: Vec::new(),
+                        text: text_from_event,
+                        bindings: bindings.last().cloned().unwrap_or_default(),
+                        instance,
+                    });
                 }
-                let current_path_string = current_path.join("/");
-                let text_from_event = e.unescape().unwrap().to_string();
-                if let Some(hashmap) = config.split.grouping.get(&current_path_string) {
-                    match hashmap.get(&text_from_event) {
-                        Some(val) => {
-                            current_group = val;   
-                        }
-                        None => {
-                            current_group = &config.split.default;
-                        }
+            }
+
+            Ok(Event::End(e)) => {
+                if superordinate(&current_path, &split_path) {
+                    for (group, writer) in writers.iter_mut() {
+                        write_events(writer, group, &[to_utf8(Event::End(e.borrow()), encoding)], msg_config, lang)?;
+                    }
+                } else {
+                    buffered.push(Buffered {
+                        event: to_utf8(Event::End(e), encoding).into_owned(),
+                        path: current_path.join("/"),
+                        attributes: Vec::new(),
+                        text: String::new(),
+                        bindings: bindings.last().cloned().unwrap_or_default(),
+                        instance: index.close(),
+                    });
+                    if current_path == split_path {
+                        splitter.replay(&mut buffered, &index, writers)?;
+                        index.clear();
                     }
                 }
-                match config.filter.allowlist.exact.get(&current_path_string) {
-                    Some(exact_list) => {
-                        if !exact_list.iter().any(|i| i.to_string() == text_from_event) {
-                            match config.filter.allowlist.regex.get(&current_path_string) {
-                                Some(regex_list) => {
-                                    if !regex_list.iter().any(
-                                        |i| Regex::new(i)
-                                        // Regex::new(r"a^") does not match "a^", so it can most likely be used as a fallback that does not match anything:
-                                        .unwrap_or_else(|_err| Regex::new(r"a^").unwrap())
-                                        .is_match(&text_from_event)
-                                    ) {
-                                        include = false;
-                                    }
-                                }
-                                None => include = false,
-                            }
-                        }
+                current_path.pop();
+                bindings.pop();
+            }
+            Ok(Event::Eof) => {
+                let msg = get_msg(msg_config, "end_of_original_file", lang);
+                info!("{msg}");
+                break
+            }
+            Ok(e) => {
+                if superordinate(&current_path, &split_path) {
+                    for (group, writer) in writers.iter_mut() {
+                        write_events(writer, group, &[to_utf8(e.borrow(), encoding)], msg_config, lang)?;
                     }
-                    None => (),
+                } else {
+                    buffered.push(Buffered {
+                        event: to_utf8(e, encoding).into_owned(),
+                        path: current_path.join("/"),
+                        attributes: Vec::new(),
+                        text: String::new(),
+                        bindings: bindings.last().cloned().unwrap_or_default(),
+                        instance: index.current(),
+                    });
                 }
-                match config.filter.blocklist.exact.get(&current_path_string) {
-                    Some(exact_list) => {
-                        if exact_list.iter().any(|i| i.to_string() == text_from_event) {
-                            include = false;
+            }
+            Err(err) => {
+                return Err(Error::Xml {
+                    msg: get_msg(msg_config, "reading_xml_event_failed", lang),
+                    position: reader.error_position(),
+                    detail: err.to_string(),
+                });
+            }
+        }
+        buf.clear();
+    }
+    let mut report = splitter.report;
+    report.transformations = splitter.transformers.into_iter()
+    .map(|t| (t.transformation.target, t.fired)).collect();
+    Ok(report)
+}
+
+/// The state of filtering, grouping and transformation while the buffered events
+/// of a split element are replayed.
+struct Splitter<'a> {
+    config: &'a Config,
+    filter: &'a CompiledFilter,
+    msg_config: &'a HashMap<String, HashMap<String, String>>,
+    transformers: Vec<Transformer>,
+    split_element: Vec<Event<'static>>,
+    found: Vec<(&'a String, &'a String)>,
+    keep: bool,
+    index: usize,
+    values: Vec<(String, String)>,
+    paths: HashSet<String>,
+    collect_paths: bool,
+    now: NaiveDateTime,
+    trace: Trace,
+    report: Report,
+}
+
+impl<'a> Splitter<'a> {
+    fn new(
+        config: &'a Config,
+        rules: &'a Rules,
+        msg_config: &'a HashMap<String, HashMap<String, String>>,
+        selection: Option<Selection>,
+        now: NaiveDateTime
+    ) -> Self {
+        Splitter {
+            config,
+            filter: &rules.filter,
+            msg_config,
+            transformers: rules.transformers.clone(),
+            split_element: Vec::new(),
+            found: Vec::new(),
+            keep: true,
+            index: 0,
+            values: Vec::new(),
+            paths: HashSet::new(),
+            // the paths of all elements and attributes are only needed for presence checks:
+            collect_paths: !rules.filter.is_empty() || !config.split.missing.is_empty() || !config.split.existing.is_empty(),
+            now,
+            trace: Trace::new(selection),
+            report: Report::default(),
+        }
+    }
+
+    /// Filters, groups and transforms the buffered events up to the end tag of a split element
+    /// and writes the split element to the files of its groups. `index` contains the elements, attributes
+    /// and values of the split element, in which the datafields and preconditions are looked up.
+    fn replay<S: Sink>(
+        &mut self,
+        buffered: &mut Vec<Buffered>,
+        index: &Index,
+        writers: &mut HashMap<String, S>
+    ) -> Result<()> {
+        let config = self.config;
+        let msg_config = self.msg_config;
+
+        for (position, item) in buffered.drain(..).enumerate() {
+            match item.event {
+                Event::Start(ref e) => {
+                    for (number, t) in self.transformers.iter_mut().enumerate() {
+                        if t.transformation.target == item.path && !t.transformation.keep && self.keep {
+                            self.keep = false;
+                            t.fired += 1;
+                            self.trace.log(|| format!("transformation {} ({}): removed", number + 1, item.path));
                         }
                     }
-                    None => {
-                        match config.filter.blocklist.regex.get(&current_path_string) {
-                            Some(regex_list) => {
-                                if regex_list.iter().any(
-                                    |i| Regex::new(i)
-                                    // Regex::new(r"a^") does not match "a^", so it can most likely be used as a fallback that does not match anything:
-                                    .unwrap_or_else(|_err| Regex::new(r"a^").unwrap())
-                                    .is_match(&text_from_event)
-                                ) {
-                                    include = false;
-                                }
-                            }
-                            None => (),
-                        }
+                    if !self.keep {
+                        continue;
+                    }
+                    if item.path == config.element {
+                        self.begin_element();
                     }
+                    if self.collect_paths {
+                        self.paths.insert(item.path.clone());
+                    }
+                    for (key, _name, value) in &item.attributes {
+                        self.inspect(key, value);
+                    }
+                    let start = self.transform_attributes(e, &item, index, position)?;
+                    self.split_element.push(Event::Start(start));
                 }
-                if superordinate(&current_path, &split_path).unwrap() {
-                    for xml_event_list in splitting.values_mut() {
-                        xml_event_list.push(Event::Text(e.clone().into_owned()));
+
+                Event::Empty(ref e) => {
+                    if !self.keep {
+                        continue;
                     }
-                } else {
-                    split_element.push(Event::Text(e.clone().into_owned()));
-                    for t in &mut transformers {
-                        t.check_value(&current_path_string, &text_from_event, &config, &msg_config);
-                        t.precondition = (t.missing.is_empty() || t.missing.clone().into_values().all(|v| v == true)) &&
-                                        (t.existing.is_empty() || t.existing.clone().into_values().all(|v| v == true));
-                        if t.transformation.target == current_path_string &&
-                            t.transformation.nodes.is_empty() &&
-                            t.precondition {
-                            split_element.pop();
-                            split_element.push(Event::Text(BytesText::new(&t.value_transformed).into_owned()));
-                        }
+                    let split = item.path == config.element;
+                    if split {
+                        self.begin_element();
+                    }
+                    if self.collect_paths {
+                        self.paths.insert(item.path.clone());
+                    }
+                    for (key, _name, value) in &item.attributes {
+                        self.inspect(key, value);
+                    }
+                    let empty = self.transform_attributes(e, &item, index, position)?;
+                    self.split_element.push(Event::Empty(empty));
+                    if split {
+                        self.finish_element(writers)?;
                     }
                 }
-            }
-   
-            Ok(Event::End(e)) => {
-                let current_path_string = current_path.join("/");
-                if !keep {
-                    for t in &mut transformers {
-                        if t.transformation.target == current_path_string {
-                            keep = true;
+
+                Event::Text(e) => {
+                    if !self.keep {
+                        continue;
+                    }
+                    let current_path_string = &item.path;
+                    let text_from_event = &item.text;
+                    self.inspect(current_path_string, text_from_event);
+                    self.split_element.push(Event::Text(e));
+                    for (number, t) in self.transformers.iter_mut().enumerate() {
+                        if &t.transformation.target == current_path_string && t.transformation.nodes.is_empty() {
+                            if t.prepare(index, position, item.instance, config, msg_config)? {
+                                self.split_element.pop();
+                                self.split_element.push(Event::Text(BytesText::new(&t.value_transformed).into_owned()));
+                                t.fired += 1;
+                                self.trace.log(|| format!(
+                                    "transformation {} ({}): {} - {:?} ➔ {:?}",
+                                    number + 1, current_path_string, t.describe_preconditions(), text_from_event, t.value_transformed
+                                ));
+                            } else {
+                                self.trace.log(|| format!(
+                                    "transformation {} ({}): {} - {:?} unchanged",
+                                    number + 1, current_path_string, t.describe_preconditions(), text_from_event
+                                ));
+                            }
                         }
                     }
-                    current_path.pop();
-                    continue;
                 }
-                if superordinate(&current_path, &split_path).unwrap() {
-                    for xml_event_list in splitting.values_mut() {
-                        xml_event_list.push(Event::End(e.clone().into_owned()));
+
+                Event::End(e) => {
+                    let current_path_string = &item.path;
+                    if !self.keep {
+                        for t in &mut self.transformers {
+                            if &t.transformation.target == current_path_string {
+                                self.keep = true;
+                            }
+                        }
+                        continue;
                     }
-                } else {
-                    for t in &mut transformers {
-                        if t.transformation.target == current_path_string {
-                            if let Some(path) = t.transformation.nodes.get("insert") {
-                                t.precondition = (t.missing.is_empty() || t.missing.clone().into_values().all(|v| v == true)) &&
-                                                (t.existing.is_empty() || t.existing.clone().into_values().all(|v| v == true));
-                                if t.precondition {
-                                    split_element.append(&mut embed(t.value_transformed.to_owned(), path.to_owned()));
+                    for (number, t) in self.transformers.iter_mut().enumerate() {
+                        if &t.transformation.target == current_path_string {
+                            if let Some(path) = t.transformation.nodes.get("insert").cloned() {
+                                let held = t.prepare(index, position, item.instance, config, msg_config)?;
+                                self.trace.log(|| match held {
+                                    true => format!(
+                                        "transformation {} ({}): {} - insert {} = {:?}",
+                                        number + 1, current_path_string, t.describe_preconditions(), path, t.value_transformed
+                                    ),
+                                    false => format!(
+                                        "transformation {} ({}): {} - {} not inserted",
+                                        number + 1, current_path_string, t.describe_preconditions(), path
+                                    ),
+                                });
+                                if held {
+                                    self.split_element.append(&mut embed(t.value_transformed.to_owned(), path));
+                                    t.fired += 1;
                                 }
                             }
                         }
                     }
-                    split_element.push(Event::End(e.clone().into_owned()));
-                    for t in &mut transformers {
-                        if t.transformation.target == current_path_string {
-                            if let Some(path) = t.transformation.nodes.get("append") {
-                                t.precondition = (t.missing.is_empty() || t.missing.clone().into_values().all(|v| v == true)) &&
-                                                (t.existing.is_empty() || t.existing.clone().into_values().all(|v| v == true));
-                                if t.precondition {
-                                    split_element.append(&mut embed(t.value_transformed.to_owned(), path.to_owned()));
+                    self.split_element.push(Event::End(e));
+                    for (number, t) in self.transformers.iter_mut().enumerate() {
+                        if &t.transformation.target == current_path_string {
+                            if let Some(path) = t.transformation.nodes.get("append").cloned() {
+                                let held = t.prepare(index, position, item.instance, config, msg_config)?;
+                                self.trace.log(|| match held {
+                                    true => format!(
+                                        "transformation {} ({}): {} - append {} = {:?}",
+                                        number + 1, current_path_string, t.describe_preconditions(), path, t.value_transformed
+                                    ),
+                                    false => format!(
+                                        "transformation {} ({}): {} - {} not appended",
+                                        number + 1, current_path_string, t.describe_preconditions(), path
+                                    ),
+                                });
+                                if held {
+                                    self.split_element.append(&mut embed(t.value_transformed.to_owned(), path));
+                                    t.fired += 1;
                                 }
                             }
-                            t.initialize_preconditions();
-                            t.value_computed = false;
                         }
                     }
-                    if current_path == split_path {
-                        if !include {
-                            current_group = &config.filter.residue;
-                        }
-                        splitting.get_mut(current_group).unwrap().append(&mut split_element);
-                    }  
+                    if current_path_string == &config.element {
+                        self.finish_element(writers)?;
+                    }
+                }
+
+                e => {
+                    if !self.keep {
+                        continue;
+                    }
+                    self.split_element.push(e);
                 }
-                current_path.pop();
             }
-            Ok(Event::Eof) => {
-                let msg = get_msg(&msg_config, "end_of_original_file", lang);
-                info!("{msg}");
-                break
+        }
+        Ok(())
+    }
+
+    /// Resets the state at the start of a split element.
+    fn begin_element(&mut self) {
+        let config = self.config;
+        self.found.clear();
+        self.index += 1;
+        self.values.clear();
+        self.paths.clear();
+        self.trace.start(self.index);
+        self.trace.log(|| format!("group: {}", config.split.default));
+    }
+
+    /// Groups and filters a complete split element and writes it to the files of its groups.
+    fn finish_element<S: Sink>(&mut self, writers: &mut HashMap<String, S>) -> Result<()> {
+        let config = self.config;
+        let msg_config = self.msg_config;
+        let lang = &config.settings.lang;
+        let mut groups = config.split.select_groups(&self.found);
+        let conflicts = config.split.conflicts(&self.found);
+        if !conflicts.is_empty() {
+            self.trace.log(|| format!(
+                "conflicting labels: {} ➔ {}",
+                conflicts.iter().map(|label| label.as_str()).collect::<Vec<&str>>().join(", "),
+                groups.iter().map(|group| group.as_str()).collect::<Vec<&str>>().join(", ")
+            ));
+            self.report.conflicts.push((self.index, conflicts.into_iter().cloned().collect()));
+        }
+        if groups == [&config.split.default] {
+            if let Some((kind, path, group)) = config.split.get_fallback(&self.paths) {
+                groups = vec![group];
+                self.trace.log(|| format!("grouping ({}): {} ➔ {}", kind, path, group));
             }
-            Ok(e) => {
-                if !keep {
-                    continue;
-                }
-                if superordinate(&current_path, &split_path).unwrap() {
-                    for xml_event_list in splitting.values_mut() {
-                        xml_event_list.push(e.clone().into_owned());
+        }
+        if self.trace.is_active() {
+            for decision in self.filter.explain(&self.values, &self.paths, self.now) {
+                self.trace.log(|| decision);
+            }
+        }
+        let violations = self.filter.violations(&self.values, &self.paths, self.now);
+        if !violations.is_empty() {
+            groups = vec![&config.filter.residue];
+            self.report.residue.push((self.index, violations));
+        }
+        let joined = groups.iter().map(|group| group.as_str()).collect::<Vec<&str>>().join(", ");
+        self.trace.finish(self.index, &joined, config, msg_config);
+        for group in groups {
+            *self.report.elements.entry(group.clone()).or_default() += 1;
+            if let Some(writer) = writers.get_mut(group) {
+                write_element(writer, group, &self.split_element, msg_config, lang)?;
+            }
+        }
+        self.split_element.clear();
+        Ok(())
+    }
+
+    /// Looks up the label of a value found at the given path, i. e. the text of an element (`a/b/c`)
+    /// or the value of an attribute (`a/b/c/@attr`), traces it and collects the label for the grouping
+    /// and the value for the filter, which are both applied at the end tag of the split element.
+    fn inspect(&mut self, path: &String, value: &String) {
+        let config = self.config;
+        self.trace.check_key(path, value);
+        if let Some((key, group)) = config.split.get_group(path, value) {
+            self.found.push((key, group));
+            self.trace.log(|| format!("grouping: {} = {:?} ➔ {}", path, value, group));
+        }
+        if !self.filter.is_empty() {
+            self.values.push((path.clone(), value.clone()));
+        }
+        if self.collect_paths {
+            self.paths.insert(path.clone());
+        }
+    }
+
+    /// Applies the transformations targeting attributes (`element/path/@attr`) of the given element:
+    /// attributes are removed if `keep` is `false`, renamed if `rename` is set and otherwise set to the
+    /// transformation value, which may be computed from datafields (including other attribute values)
+    /// looked up in the scope of the attribute.
+    /// An empty `value` keeps the original attribute value. New and renamed attributes get the prefix
+    /// the document binds to the namespace of their configured prefix (see `document_name`).
+    fn transform_attributes(
+        &mut self,
+        e: &BytesStart,
+        item: &Buffered,
+        index: &Index,
+        position: usize
+    ) -> Result<BytesStart<'static>> {
+        let (config, msg_config) = (self.config, self.msg_config);
+        let (element_path, instance, bindings) = (&item.path, item.instance, &item.bindings);
+        let mut transformed: Vec<(String, String, String)> = item.attributes.to_vec();
+        let mut declarations: Vec<(String, String)> = Vec::new();
+        let mut applied = false;
+
+        for (number, t) in self.transformers.iter_mut().enumerate() {
+            let (attribute, new_name) = match attribute_key(&t.transformation.target) {
+                Some((path, name)) if path == element_path => (t.transformation.target.clone(), name.to_string()),
+                _ => continue,
+            };
+            if t.check_preconditions(index, instance) {
+                let original = transformed.iter().find(|(key, _, _)| key == &attribute).map(|(_, _, value)| value.clone());
+                if !t.transformation.keep {
+                    if original.is_some() {
+                        transformed.retain(|(key, _, _)| key != &attribute);
+                        applied = true;
+                        t.fired += 1;
+                        self.trace.log(|| format!("transformation {} ({}): {} - removed", number + 1, attribute, t.describe_preconditions()));
                     }
                 } else {
-                    split_element.push(e.into_owned());
+                    let value = if t.transformation.value.is_empty() {
+                        original.clone()
+                    } else {
+                        t.compute_value(index, position, instance, config, msg_config)?;
+                        if t.value_computed { Some(t.value_transformed.clone()) } else { None }
+                    };
+                    if let Some(value) = value {
+                        self.trace.log(|| format!(
+                            "transformation {} ({}): {} - {:?} ➔ {}{:?}",
+                            number + 1, attribute, t.describe_preconditions(), original.unwrap_or_default(),
+                            if t.transformation.rename.is_empty() { String::new() } else { format!("@{} = ", t.transformation.rename) },
+                            value
+                        ));
+                        if t.transformation.rename.is_empty() {
+                            match transformed.iter_mut().find(|(key, _, _)| key == &attribute) {
+                                Some(entry) => entry.2 = value,
+                                None => {
+                                    let name = document_name(&new_name, bindings, &config.namespaces, &mut declarations);
+                                    transformed.push((attribute, name, value));
+                                }
+                            }
+                        } else {
+                            let rename = &t.transformation.rename;
+                            let renamed = format!("{}/@{}", element_path, rename);
+                            // a renamed attribute replaces any other attribute already carrying the new name:
+                            if renamed != attribute {
+                                transformed.retain(|(key, _, _)| key != &renamed);
+                            }
+                            let name = document_name(rename, bindings, &config.namespaces, &mut declarations);
+                            match transformed.iter_mut().find(|(key, _, _)| key == &attribute) {
+                                Some(entry) => *entry = (renamed, name, value),
+                                None => transformed.push((renamed, name, value)),
+                            }
+                        }
+                        applied = true;
+                        t.fired += 1;
+                    }
                 }
+            } else {
+                self.trace.log(|| format!("transformation {} ({}): {} - unchanged", number + 1, attribute, t.describe_preconditions()));
             }
-            Err(err) => {
-                let msg = get_msg(&msg_config, "reading_xml_event_failed", lang);
-                error!("{msg}: {err}");
-                break
+        }
+
+        if !applied {
+            return Ok(e.clone().into_owned());
+        }
+        let mut start = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).to_string());
+        for (_key, name, value) in &transformed {
+            start.push_attribute((name.as_str(), value.as_str()));
+        }
+        for (prefix, uri) in &declarations {
+            start.push_attribute((format!("xmlns:{prefix}").as_str(), uri.as_str()));
+        }
+        Ok(start)
+    }
+}
+
+/// Returns the name of an element or an attribute as used in the configuration paths:
+/// if namespaces are configured, a name bound to one of the configured namespace URIs
+/// gets the corresponding configured prefix, no matter which prefix the document uses.
+/// Otherwise the qualified name is taken as it is.
+fn qualified_name(
+    resolved: (ResolveResult, &[u8]),
+    name: QName,
+    prefixes: &HashMap<&String, &String>,
+    encoding: &'static Encoding
+) -> String {
+    if !prefixes.is_empty() {
+        let (resolve_result, local_name) = resolved;
+        let local_name = decode(local_name, encoding);
+        match resolve_result {
+            ResolveResult::Bound(ns) => {
+                let uri = decode(ns.as_ref(), encoding);
+                match prefixes.get(&uri) {
+                    Some(prefix) if prefix.is_empty() => return local_name.to_string(),
+                    Some(prefix) => return format!("{}:{}", prefix, local_name),
+                    None => (),
+                }
             }
+            ResolveResult::Unbound => return local_name.to_string(),
+            ResolveResult::Unknown(_) => (),
         }
-        buf.clear();
     }
-    Ok(splitting)
-}
\ No newline at end of file
+    decode(name.as_ref(), encoding)
+}
+
+/// Decodes names and contents read in the encoding of the original file (replacing malformed sequences).
+fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+/// Converts an event read in the encoding of the original file into UTF-8, so that all events
+/// of the split elements and of the output can be handled alike. The encoding of the XML
+/// declaration is changed to UTF-8 accordingly. Events read from UTF-8 input are returned unchanged.
+fn to_utf8<'a>(event: Event<'a>, encoding: &'static Encoding) -> Event<'a> {
+    if encoding == UTF_8 {
+        return event;
+    }
+    match event {
+        Event::Start(e) => Event::Start(BytesStart::from_content(decode(&e, encoding), decode(e.name().as_ref(), encoding).len())),
+        Event::Empty(e) => Event::Empty(BytesStart::from_content(decode(&e, encoding), decode(e.name().as_ref(), encoding).len())),
+        Event::End(e) => Event::End(BytesEnd::new(decode(e.name().as_ref(), encoding))),
+        Event::Text(e) => Event::Text(BytesText::from_escaped(decode(&e, encoding))),
+        Event::CData(e) => Event::CData(BytesCData::new(decode(&e, encoding))),
+        Event::Comment(e) => Event::Comment(BytesText::from_escaped(decode(&e, encoding))),
+        Event::PI(e) => Event::PI(BytesPI::new(decode(&e, encoding))),
+        Event::DocType(e) => Event::DocType(BytesText::from_escaped(decode(&e, encoding))),
+        Event::Decl(e) => {
+            let version = e.version().map(|version| decode(&version, encoding)).unwrap_or("1.0".to_string());
+            let standalone = e.standalone().and_then(|standalone| standalone.ok()).map(|standalone| decode(&standalone, encoding));
+            Event::Decl(BytesDecl::new(&version, Some("UTF-8"), standalone.as_deref()).into_owned())
+        }
+        Event::Eof => Event::Eof,
+    }
+}
+
+fn element_name<R: BufRead>(reader: &NsReader<R>, name: QName, prefixes: &HashMap<&String, &String>) -> String {
+    let (resolve_result, local_name) = reader.resolve_element(name);
+    qualified_name((resolve_result, local_name.into_inner()), name, prefixes, reader.decoder().encoding())
+}
+
+/// Collects the attributes of a start tag or an empty element tag as triples consisting of
+/// the key (a path of the form `element/path/@attr`), the attribute name in the document
+/// and the attribute value.
+fn get_attributes<R: BufRead>(
+    e: &BytesStart,
+    element_path: &String,
+    reader: &NsReader<R>,
+    prefixes: &HashMap<&String, &String>,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> Result<Vec<(String, String, String)>> {
+    let xml_error = |context: &String, detail: String| Error::Xml {
+        msg: get_msg(msg_config, "reading_xml_event_failed", &config.settings.lang),
+        position: reader.buffer_position(),
+        detail: format!("{context} - {detail}"),
+    };
+    let mut attributes: Vec<(String, String, String)> = Vec::new();
+    for attribute in e.attributes() {
+        let attr = attribute.map_err(|err| xml_error(element_path, err.to_string()))?;
+        let (resolve_result, local_name) = reader.resolve_attribute(attr.key);
+        let name = qualified_name((resolve_result, local_name.into_inner()), attr.key, prefixes, reader.decoder().encoding());
+        let key = format!("{}/@{}", element_path, name);
+        let value = attr.decode_and_unescape_value(reader.decoder()).map_err(|err| xml_error(&key, err.to_string()))?;
+        attributes.push((
+            key,
+            decode(attr.key.as_ref(), reader.decoder().encoding()),
+            value.to_string()
+        ));
+    }
+    Ok(attributes)
+}
+
+/// Returns the namespace prefixes in scope of the given element: the prefixes in scope of its parent
+/// together with the named prefixes (`xmlns:prefix="uri"`) the element declares itself.
+fn bind_prefixes(e: &BytesStart, parent: Option<&Rc<Bindings>>, encoding: &'static Encoding) -> Rc<Bindings> {
+    let mut declared: Bindings = Vec::new();
+    for attr in e.attributes().flatten() {
+        if let Some(PrefixDeclaration::Named(prefix)) = attr.key.as_namespace_binding() {
+            declared.push((decode(prefix, encoding), decode(&attr.value, encoding)));
+        }
+    }
+    match parent {
+        Some(parent) if declared.is_empty() => Rc::clone(parent),
+        Some(parent) => Rc::new(parent.iter().cloned().chain(declared).collect()),
+        None => Rc::new(declared),
+    }
+}
+
+/// Returns the name under which an attribute named as in the configuration (e. g. by the target
+/// or `rename` of a transformation) is written into the document: a prefix of the `namespaces` table
+/// is replaced by a prefix the document binds to its namespace URI in the scope of the element.
+/// If there is none, the configured prefix (numbered if the document binds it to another URI)
+/// is declared by adding it to `declarations`. Other names are taken as they are.
+fn document_name(
+    name: &str,
+    bindings: &Bindings,
+    namespaces: &HashMap<String, String>,
+    declarations: &mut Vec<(String, String)>
+) -> String {
+    let Some((prefix, local_name)) = name.split_once(':') else {
+        return name.to_string();
+    };
+    let Some(uri) = namespaces.get(prefix).filter(|_| !prefix.is_empty()) else {
+        return name.to_string();
+    };
+    // the innermost declaration of a prefix hides the outer ones:
+    let mut hidden: HashSet<&String> = HashSet::new();
+    for (bound, bound_uri) in bindings.iter().rev() {
+        if hidden.insert(bound) && bound_uri == uri {
+            return format!("{bound}:{local_name}");
+        }
+    }
+    if let Some((declared, _)) = declarations.iter().find(|(_, declared_uri)| declared_uri == uri) {
+        return format!("{declared}:{local_name}");
+    }
+    let taken = |candidate: &String| hidden.contains(candidate) || declarations.iter().any(|(declared, _)| declared == candidate);
+    let mut declared = prefix.to_string();
+    let mut number = 0;
+    while taken(&declared) {
+        number += 1;
+        declared = format!("{prefix}{number}");
+    }
+    declarations.push((declared.clone(), uri.clone()));
+    format!("{declared}:{local_name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<inv:payments xmlns:inv="urn:invoices" xmlns:x="urn:other">
+        <invoice xmlns="urn:invoices" inv:id="1" x:id="2" id="3"/></inv:payments>"#;
+
+    /// The names of the elements and attributes of `XML` as seen with the given configured namespaces.
+    fn names(namespaces: &[(&str, &str)]) -> Vec<String> {
+        let namespaces: HashMap<String, String> = namespaces.iter()
+        .map(|(prefix, uri)| (prefix.to_string(), uri.to_string()))
+        .collect();
+        let prefixes: HashMap<&String, &String> = namespaces.iter().map(|(prefix, uri)| (uri, prefix)).collect();
+        let mut reader = NsReader::from_str(XML);
+        let mut names = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(e) | Event::Empty(e) => {
+                    names.push(element_name(&reader, e.name(), &prefixes));
+                    for attr in e.attributes() {
+                        let key = attr.unwrap().key;
+                        let (resolve_result, local_name) = reader.resolve_attribute(key);
+                        let name = qualified_name((resolve_result, local_name.into_inner()), key, &prefixes, reader.decoder().encoding());
+                        names.push(format!("@{name}"));
+                    }
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+        names
+    }
+
+    #[test]
+    fn names_are_taken_as_they_are_without_namespaces() {
+        assert_eq!(
+            names(&[]),
+            ["inv:payments", "@xmlns:inv", "@xmlns:x", "invoice", "@xmlns", "@inv:id", "@x:id", "@id"]
+        );
+    }
+
+    #[test]
+    fn names_get_the_configured_prefix_of_their_namespace() {
+        let names = names(&[("p", "urn:invoices")]);
+        // the default namespace does not apply to attributes, unknown namespaces keep their prefix:
+        assert_eq!(names[0], "p:payments");
+        assert_eq!(names[3], "p:invoice");
+        assert_eq!(names[5..], ["@p:id", "@x:id", "@id"]);
+    }
+
+    #[test]
+    fn an_empty_configured_prefix_drops_the_prefix() {
+        let names = names(&[("", "urn:invoices"), ("o", "urn:other")]);
+        assert_eq!(names[0], "payments");
+        assert_eq!(names[3], "invoice");
+        assert_eq!(names[5..], ["@id", "@o:id", "@id"]);
+    }
+}
@@ -1,11 +1,15 @@
-use log::{error, warn};
+use log::warn;
 use serde::Deserialize;
 use std::collections::HashMap;
 use evalexpr::*;
 
-use crate::{get_msg, Config};
+use crate::common::get_msg;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::transformations::scope::Index;
 
 #[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct Source {
     pub datafields: HashMap<String, String>,
     pub literals: HashMap<String, String>,
@@ -14,12 +18,24 @@ pub struct Source {
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct Transformation {
     pub target: String,
+    #[serde(default = "keep_by_default")]
     pub keep: bool,
+    #[serde(default)]
     pub value: String,
+    #[serde(default)]
     pub nodes: HashMap<String, String>,
+    #[serde(default)]
     pub source: Source,
+    #[serde(default)]
     pub parameters: HashMap<String, String>,
+    #[serde(default)]
     pub preconditions: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub rename: String,
+}
+
+fn keep_by_default() -> bool {
+    true
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -28,121 +44,191 @@ pub struct Transformer {
     pub parameters: HashMap<String, String>,
     pub value_computed: bool,
     pub value_transformed: String,
+    /// Whether `value_transformed` holds the value evaluated for the current `parameters`.
+    pub evaluated: bool,
     pub missing: HashMap<String, bool>,
     pub existing: HashMap<String, bool>,
     pub precondition: bool,
+    pub fired: usize,
+    pub number: usize,
+    /// The expression tree of the value, built once by `compile`.
+    #[serde(skip)]
+    pub node: Option<Node>,
 }
 
 impl Transformer {
-    pub fn new(transformation: Transformation) -> Self {
+    /// Creates the transformer for the transformation rule with the given number (starting at 1).
+    pub fn new(number: usize, transformation: Transformation) -> Self {
         Transformer {
             transformation,
+            number,
             precondition: true,
             ..Default::default()
         }
     }
 
-    pub fn initialize_preconditions(&mut self) {
+    /// Builds the expression tree of the value, so that it only has to be evaluated for each target.
+    /// Values without datafields and literals are inserted as string literals and need no expression tree.
+    pub fn compile(mut self, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) -> Result<Self> {
+        let source = &self.transformation.source;
+        if !source.datafields.is_empty() || !source.literals.is_empty() {
+            let node = build_operator_tree(&self.transformation.value).map_err(|err| Error::Config {
+                msg: get_msg(msg_config, "evaluation_failed", lang),
+                path: format!("transformations #{} ({})", self.number, self.transformation.target),
+                detail: format!("{} - {err}", self.transformation.value),
+            })?;
+            self.node = Some(node);
+        }
+        Ok(self)
+    }
+
+    /// Checks the preconditions for an occurrence of the target, given by the element instance it belongs to:
+    /// each path is looked up in the scope of the target (see `Index`), e. g. a `missing` quantity
+    /// of an invoice line for a target inside the same line.
+    pub fn check_preconditions(&mut self, index: &Index, instance: usize) -> bool {
+        let target = &self.transformation.target;
+        self.missing.clear();
+        self.existing.clear();
         if let Some(elements) = self.transformation.preconditions.get("missing") {
             for element in elements {
-                *self.missing.entry(element.clone()).or_insert(true) = true;
+                self.missing.insert(element.clone(), !index.exists(element, target, instance));
             }
         }
         if let Some(elements) = self.transformation.preconditions.get("existing") {
             for element in elements {
-                *self.existing.entry(element.clone()).or_insert(false) = false;
+                self.existing.insert(element.clone(), index.exists(element, target, instance));
             }
         }
+        self.precondition = self.missing.values().all(|v| *v) && self.existing.values().all(|v| *v);
+        self.precondition
     }
-    pub fn eval_expr(&mut self, config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> () {   
-        let evaluation_error = get_msg(&msg_config, "evaluation_failed", &config.settings.lang);
-                match build_operator_tree(&self.transformation.value) {
-                    Ok(node) => {
-                        let mut context = HashMapContext::new();
-                        for (var, val) in &self.parameters {
-                            let v = val.clone();
-                            if let Ok(i) = v.parse::<i64>() {
-                                context.set_value(var.as_str().into(), i.into()).unwrap();
 
-                            } else if let Ok(f) = v.parse::<f64>() {
-                                context.set_value(var.as_str().into(), f.into()).unwrap();
-                            } else {
-                                context.set_value(var.as_str().into(), v.to_string().into()).unwrap();
-                            }
-                        }
+    /// Checks the preconditions for an occurrence of the target and, if they hold, computes its value.
+    /// Returns whether the target is to be transformed.
+    pub fn prepare(
+        &mut self,
+        index: &Index,
+        position: usize,
+        instance: usize,
+        config: &Config,
+        msg_config: &HashMap<String, HashMap<String, String>>
+    ) -> Result<bool> {
+        self.value_computed = false;
+        if self.check_preconditions(index, instance) {
+            self.compute_value(index, position, instance, config, msg_config)?;
+        }
+        Ok(self.precondition && self.value_computed)
+    }
 
-                        match node.eval_with_context_mut(&mut context) {
-                            Ok(v) => {
-                                match v {
-                                    Value::Float(f) => {
-                                        if let Some(num_str) = self.transformation.parameters.get("decimal_places") {
-                                            match num_str.parse::<usize>() {
-                                                Ok(num) => self.value_transformed = format!("{:.1$}", f, num),
-                                                Err(err) => {
-                                                    self.value_transformed = num_str.to_string();
-                                                    let warning = get_msg(&msg_config, "decimal_places_not_parsable", &config.settings.lang);
-                                                    warn!("{warning}: {num_str} - {err}"); 
-                                                }
-                                            }
-                                        } else {
-                                            self.value_transformed = f.to_string(); 
-                                        }
-                                    },
-                                    Value::Int(i) => {
-                                        if let Some(num_str) = self.transformation.parameters.get("decimal_places") {
-                                            match num_str.parse::<usize>() {
-                                                Ok(num) => self.value_transformed = format!("{:.1$}", i as f64, num),
-                                                Err(err) => {
-                                                    self.value_transformed = num_str.to_string();
-                                                    let warning = get_msg(&msg_config, "decimal_places_not_parsable", &config.settings.lang);
-                                                    warn!("{warning}: {num_str} - {err}");
-                                                }
-                                            }
-                                        } else {
-                                            self.value_transformed = i.to_string(); 
-                                        }
-                                    },
-                                    Value::Empty => {
-                                        self.value_transformed = "".to_string();
-                                        let warning = get_msg(&msg_config, "empty_value", &config.settings.lang);
-                                        warn!("{warning}: {v}");
-                                    }
-                                    _ => {
-                                        let value = v.to_string();
-                                        match value.strip_prefix('"') {
-                                            Some(prefixless) => {
-                                                match prefixless.strip_suffix('"') {
-                                                    Some(suffixless) => {
-                                                        self.value_transformed = suffixless.to_string();
-                                                    }
-                                                    None => self.value_transformed = value,
-                                                }
-                                            },
-                                            None => self.value_transformed = value,
-                                        }
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                error!("{evaluation_error}: {err}");
-                                panic!();
-                            }
+    /// Describes the state of the preconditions (used to trace the transformations).
+    pub fn describe_preconditions(&self) -> String {
+        let mut states: Vec<String> = Vec::new();
+        for (kind, state) in [("missing", &self.missing), ("existing", &self.existing)] {
+            let mut elements = state.iter()
+            .map(|(element, held)| format!("{} {}", element, if *held { "✔" } else { "✘" }))
+            .collect::<Vec<String>>();
+            elements.sort();
+            if !elements.is_empty() {
+                states.push(format!("{}: [{}]", kind, elements.join(", ")));
+            }
+        }
+        let held = if self.precondition { "held" } else { "failed" };
+        if states.is_empty() {
+            format!("preconditions {held}")
+        } else {
+            format!("preconditions {held} ({})", states.join(", "))
+        }
+    }
+
+    /// Evaluates the expression tree built by `compile` with the current `parameters`.
+    pub fn eval_expr(&mut self, config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Result<()> {
+        let evaluation_error = |err: EvalexprError| Error::Evaluation {
+            msg: get_msg(msg_config, "evaluation_failed", &config.settings.lang),
+            rule: self.number,
+            target: self.transformation.target.clone(),
+            detail: err.to_string(),
+        };
+        // the expression trees are built before the original files are read, never while they are transformed:
+        let Some(node) = &self.node else {
+            return Err(evaluation_error(EvalexprError::CustomMessage("the expression was not compiled".to_string())));
+        };
+        let mut context = HashMapContext::new();
+        for (var, val) in &self.parameters {
+            let v = val.clone();
+            if let Ok(i) = v.parse::<i64>() {
+                context.set_value(var.as_str().into(), i.into()).map_err(evaluation_error)?;
+            } else if let Ok(f) = v.parse::<f64>() {
+                context.set_value(var.as_str().into(), f.into()).map_err(evaluation_error)?;
+            } else {
+                context.set_value(var.as_str().into(), v.to_string().into()).map_err(evaluation_error)?;
+            }
+        }
+
+        let v = node.eval_with_context_mut(&mut context).map_err(evaluation_error)?;
+        match v {
+            Value::Float(f) => {
+                if let Some(num_str) = self.transformation.parameters.get("decimal_places") {
+                    match num_str.parse::<usize>() {
+                        Ok(num) => self.value_transformed = format!("{:.1$}", f, num),
+                        Err(err) => {
+                            self.value_transformed = num_str.to_string();
+                            let warning = get_msg(msg_config, "decimal_places_not_parsable", &config.settings.lang);
+                            warn!("{warning}: {num_str} - {err}");
                         }
                     }
-                    Err(err) => {
-                        error!("{evaluation_error}: {err}");
-                        panic!();
+                } else {
+                    self.value_transformed = f.to_string();
+                }
+            },
+            Value::Int(i) => {
+                if let Some(num_str) = self.transformation.parameters.get("decimal_places") {
+                    match num_str.parse::<usize>() {
+                        Ok(num) => self.value_transformed = format!("{:.1$}", i as f64, num),
+                        Err(err) => {
+                            self.value_transformed = num_str.to_string();
+                            let warning = get_msg(msg_config, "decimal_places_not_parsable", &config.settings.lang);
+                            warn!("{warning}: {num_str} - {err}");
+                        }
                     }
+                } else {
+                    self.value_transformed = i.to_string();
+                }
+            },
+            Value::Empty => {
+                self.value_transformed = "".to_string();
+                let warning = get_msg(msg_config, "empty_value", &config.settings.lang);
+                warn!("{warning}: {v}");
+            }
+            _ => {
+                let value = v.to_string();
+                match value.strip_prefix('"') {
+                    Some(prefixless) => {
+                        match prefixless.strip_suffix('"') {
+                            Some(suffixless) => {
+                                self.value_transformed = suffixless.to_string();
+                            }
+                            None => self.value_transformed = value,
+                        }
+                    },
+                    None => self.value_transformed = value,
                 }
+            }
+        }
+        Ok(())
     }
 
-    pub fn check_value(
+    /// Computes the value for an occurrence of the target, given by the position of its event in the split
+    /// element and the element instance it belongs to: each datafield takes the value found in the scope
+    /// of the target (see `Index`). If a datafield has no value, the value isn't computed; if no datafield
+    /// changed since the last occurrence, the value evaluated then is kept.
+    pub fn compute_value(
         &mut self,
-        current_path_string: &String,
-        text_from_event: &String,
+        index: &Index,
+        position: usize,
+        instance: usize,
         config: &Config,
         msg_config: &HashMap<String, HashMap<String, String>>
-    ) -> () {
+    ) -> Result<()> {
         let datafields = &self.transformation.source.datafields;
         let literals = &self.transformation.source.literals;
         // If no sources are specified for the assignment of the variables, it is assumed
@@ -150,25 +236,31 @@ impl Transformer {
         if datafields.is_empty() && literals.is_empty() {
             self.value_transformed = self.transformation.value.to_string();
             self.value_computed = true;
-            return;
+            return Ok(());
         }
-        for (k, v) in literals {
-            if self.parameters.get(k).is_none() {
-                self.parameters.insert(k.to_string(), v.to_string());
+        self.value_computed = false;
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        for (k, v) in datafields {
+            if let Some(value) = index.value(v, &self.transformation.target, position, instance) {
+                parameters.insert(k.to_string(), value.clone());
             }
         }
-        for (k, v) in datafields {
-            if v == current_path_string {
-                if self.parameters.get(k).is_none() {
-                    self.parameters.insert(k.to_string(), text_from_event.clone());
-                } else {
-                    *self.parameters.get_mut(k).unwrap() = text_from_event.clone();
-                }
+        for (k, v) in literals {
+            if !parameters.contains_key(k) {
+                parameters.insert(k.to_string(), v.to_string());
             }
         }
-        if self.parameters.len() == datafields.len() + literals.len() {
-            self.eval_expr(&config, &msg_config);
-            self.value_computed = true;
+        if parameters.len() != datafields.len() + literals.len() {
+            return Ok(());
+        }
+        // The expression is only evaluated again if a parameter changed since the last evaluation:
+        if !self.evaluated || parameters != self.parameters {
+            self.parameters = parameters;
+            self.evaluated = false;
+            self.eval_expr(config, msg_config)?;
+            self.evaluated = true;
         }
+        self.value_computed = true;
+        Ok(())
     }
 }
\ No newline at end of file
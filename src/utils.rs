@@ -1,37 +1,41 @@
 use std::collections::hash_map::RandomState;
 use std::env;
-use std::fs::{self, DirEntry};
+use std::fs::{self, DirEntry, File};
 use std::fs::{read_dir, remove_dir_all};
-use std::io::prelude::*;
-use std::io::Result;
-use std::path::Path;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::collections::{HashMap, HashSet};
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use lettre::message::{header, Mailbox, Mailboxes, MessageBuilder};
 use log::{error, info, warn};
-use quick_xml::events::{Event, BytesStart, BytesText, BytesEnd};
-use quick_xml::writer::Writer;
+use regex::Regex;
 use lettre::message::header::ContentType;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
-use crate::config::*;
+use xtract::config::*;
+use xtract::output::Sink;
+use xtract::compression::*;
+use xtract::error::{Error, Result};
+use xtract::transformations::filter::attribute_key;
+pub use xtract::common::*;
 
 pub fn update_sliding_window(
     hist_dir: &Path,
     folder: &DirEntry,
     current_date: NaiveDate,
     storage_period: &usize,
-    time_format: &String,
+    time_format: &str,
     msg_config: &HashMap<String, HashMap<String, String>>,
     lang: &String
-) -> () {
+) {
     if let Some(folder_name) = folder
     .file_name()
     .to_str() {
         match NaiveDate::parse_from_str(
             folder_name,
-            &time_format
+            time_format
         ) {
             Ok(folder_date) => {
                 let diff = current_date - folder_date;
@@ -39,7 +43,7 @@ pub fn update_sliding_window(
                     match remove_dir_all(hist_dir.join(folder_name)) {
                         Ok(_) => {
                         let msg = get_msg(
-                            &msg_config,
+                            msg_config,
                             "history_cleared",
                             lang
                         );
@@ -47,7 +51,7 @@ pub fn update_sliding_window(
                     }
                         Err(err) => {
                             let msg = get_msg(
-                                &msg_config,
+                                msg_config,
                                 "history_clearing_failed",
                                 lang
                             );
@@ -58,11 +62,11 @@ pub fn update_sliding_window(
             }
             Err(err) => {
                 let msg = get_msg(
-                    &msg_config,
+                    msg_config,
                     "parse_date_from_folder_name_failed",
                     lang
                 );
-                error!("{}", format!("{}: {:?} - {}", msg, folder, err));
+                error!("{}: {:?} - {}", msg, folder, err);
             }
         }
     }
@@ -71,12 +75,12 @@ pub fn update_sliding_window(
 pub fn check_history(
     hist_dir: &Path,
     storage_period: &usize,
-    time_format: &String,
-    timestamp: &String,
+    time_format: &str,
+    timestamp: &str,
     msg_config: &HashMap<String, HashMap<String, String>>,
     lang: &String
-) -> () {
-    match NaiveDate::parse_from_str(&timestamp, &time_format) {
+) {
+    match NaiveDate::parse_from_str(timestamp, time_format) {
         Ok(current_date) => {
             match read_dir(hist_dir) {
                 Ok(hist_folders) => {
@@ -94,62 +98,39 @@ pub fn check_history(
                                 );
                             }
                             Err(err) => {
-                                let msg = get_msg(&msg_config, "reading_dir_entry_failed", lang);
-                                error!("{}", format!("{}: {:?} - {}", msg, entry, err));
+                                let msg = get_msg(msg_config, "reading_dir_entry_failed", lang);
+                                error!("{}: {:?} - {}", msg, entry, err);
                             }
                         }
                     }
                 }
                 Err(err) => {
-                    let msg = get_msg(&msg_config, "reading_hist_dir_failed", lang);
-                    error!("{}", format!("{}: {:?} - {}", msg, hist_dir, err));
+                    let msg = get_msg(msg_config, "reading_hist_dir_failed", lang);
+                    error!("{}: {:?} - {}", msg, hist_dir, err);
                 }
             }
         }
         Err(err) => {
-            let msg = get_msg(&msg_config, "parse_date_from_timestamp_failed", lang);
-            error!("{}", format!("{}: {:?} - {}", msg, hist_dir, err));
+            let msg = get_msg(msg_config, "parse_date_from_timestamp_failed", lang);
+            error!("{}: {:?} - {}", msg, hist_dir, err);
         }
     }
 }
 
 pub fn get_intersection(hs1: &HashSet<&String, RandomState>, hs2: &HashSet<&String, RandomState>) -> Vec<String> {
-    let intersection: Vec<String> = hs1.intersection(&hs2)
-    .into_iter()
+    let intersection: Vec<String> = hs1.intersection(hs2)
     .map(|s| s.to_string())
     .collect();
     intersection
 }
 
 pub fn get_difference(hs1: &HashSet<&String, RandomState>, hs2: &HashSet<&String, RandomState>) -> Vec<String> {
-    let difference: Vec<String> = hs1.difference(&hs2)
-    .into_iter()
+    let difference: Vec<String> = hs1.difference(hs2)
     .map(|s| s.to_string())
     .collect();
     difference
 }
 
-pub fn embed<'a>(value: String, path: String) -> Vec<Event<'a>> {
-    let mut new_element_names: Vec<String> = Vec::new();
-    let mut new_elements: Vec<Event<'_>> = Vec::new();
-    let split_path= path.split("/");
-    for name in split_path {
-        new_element_names.push(name.to_string());
-    }
-    for element in &new_element_names {
-        let start_tag = BytesStart::new( element.clone());
-        new_elements.push(Event::Start(start_tag));
-    }
-    new_elements.push(Event::Text(BytesText::new(&value).into_owned()));
-    new_element_names.reverse();
-    for name in new_element_names {
-        let end_tag = BytesEnd::new(name.clone());
-        new_elements.push(Event::End(end_tag));
-    }
-
-    new_elements
-}
-
 pub fn format_warning(
     inconsistent_values: (&String, Vec<String>),
     msg_config: &HashMap<String, HashMap<String, String>>,
@@ -158,8 +139,8 @@ pub fn format_warning(
     let (xml_element, conflicting_values) = inconsistent_values;
 
     let warning = [
-        format!("  {}: {}", get_msg(&msg_config, "xml_element", lang), xml_element),
-        format!("  {}: {}", get_msg(&msg_config, "values", lang), &conflicting_values.join(", ")),
+        format!("  {}: {}", get_msg(msg_config, "xml_element", lang), xml_element),
+        format!("  {}: {}", get_msg(msg_config, "values", lang), &conflicting_values.join(", ")),
     ].join("\n");
     
     warning
@@ -178,7 +159,35 @@ pub fn compile_warnings(
     compiled_warnings.join("\n  ---\n")
 }
 
+pub fn check_attribute_keys(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) {
+    let mut invalid_keys: Vec<&String> = config.filter.allowlist.exact.keys()
+    .chain(config.filter.allowlist.regex.keys())
+    .chain(config.filter.blocklist.exact.keys())
+    .chain(config.filter.blocklist.regex.keys())
+    .chain(config.split.grouping.keys())
+    .chain(config.filter.presence.existing.iter())
+    .chain(config.filter.presence.missing.iter())
+    .chain(config.split.missing.keys())
+    .chain(config.split.existing.keys())
+    .filter(|key| key.contains('@') && attribute_key(key).is_none())
+    .collect();
+    invalid_keys.sort_unstable();
+    invalid_keys.dedup();
+
+    if !invalid_keys.is_empty() {
+        let msg = get_msg(msg_config, "invalid_attribute_key", lang);
+        let list: Vec<String> = invalid_keys.into_iter().map(|key| key.to_string()).collect();
+        let warning = format!("{}:\n  • {}", msg, list.join("\n  • "));
+        warn!("{warning}");
+        if config.settings.inconsistency_notification {
+            let _ = send_mail(config, msg_config, warning);
+        }
+    }
+}
+
 pub fn check_consistency(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, lang: &String) {
+    check_attribute_keys(config, msg_config, lang);
+
     let keys_allow_exact: HashSet<&String, RandomState> = HashSet::from_iter(config.filter.allowlist.exact.keys());
     let keys_allow_regex: HashSet<&String, RandomState> = HashSet::from_iter(config.filter.allowlist.regex.keys());
     let keys_allow = keys_allow_exact.union(&keys_allow_regex).cloned().collect();
@@ -193,26 +202,26 @@ pub fn check_consistency(config: &Config, msg_config: &HashMap<String, HashMap<S
         let mut allowance_without_splitting: Vec<(&String, Vec<String>)> = Vec::new();
         let mut splitting_despite_blocking: Vec<(&String, Vec<String>)> = Vec::new();
 
-        for (xml_path, _labeling) in &config.split.grouping {
+        for xml_path in config.split.grouping.keys() {
             if config.split.grouping.contains_key(xml_path) {
                 let splitting = HashSet::from_iter(
                     config.split.grouping
                     .get(xml_path)
-                    .unwrap()
-                    .keys()
+                    .into_iter()
+                    .flat_map(|labeling| labeling.keys())
                 );
                 if keys_allow.contains(xml_path) {
                     let allowed_exact: HashSet<&String> = HashSet::from_iter(
                         config.filter.allowlist.exact
                         .get(xml_path)
-                        .unwrap()
                         .into_iter()
+                        .flatten()
                     );
                     let allowed_regex: HashSet<&String> = HashSet::from_iter(
                         config.filter.allowlist.regex
                         .get(xml_path)
-                        .unwrap()
                         .into_iter()
+                        .flatten()
                     );
                     let allowed = allowed_exact.union(&allowed_regex).cloned().collect();
                     let split_but_not_allowed: Vec<String> = get_difference(&splitting, &allowed);
@@ -228,8 +237,8 @@ pub fn check_consistency(config: &Config, msg_config: &HashMap<String, HashMap<S
                     let blocked = HashSet::from_iter(
                         config.filter.blocklist.exact
                         .get(xml_path)
-                        .unwrap()
                         .into_iter()
+                        .flatten()
                     );
                     let split_while_blocked: Vec<String> = get_intersection(&splitting, &blocked);
                     if !split_while_blocked.is_empty() {
@@ -242,310 +251,245 @@ pub fn check_consistency(config: &Config, msg_config: &HashMap<String, HashMap<S
         let mut collected_warnings: Vec<String> = Vec::new();
 
         if !splitting_without_allowance.is_empty() {
-            let msg = get_msg(&msg_config, "splitting_without_allowance", lang);
+            let msg = get_msg(msg_config, "splitting_without_allowance", lang);
             let warning = format!("• {}:\n\n{}\n", msg, compile_warnings(splitting_without_allowance, msg_config, lang));
             collected_warnings.push(warning);
         }
         if !splitting_despite_blocking.is_empty() {
-            let msg = get_msg(&msg_config, "splitting_despite_blocking", lang);
+            let msg = get_msg(msg_config, "splitting_despite_blocking", lang);
             let warning = format!("• {}:\n\n{}\n", msg, compile_warnings(splitting_despite_blocking, msg_config, lang));
             collected_warnings.push(warning);
         }
         if !allowance_without_splitting.is_empty() {
-            let msg = get_msg(&msg_config, "allowance_without_splitting", lang);
+            let msg = get_msg(msg_config, "allowance_without_splitting", lang);
             let warning = format!("• {}:\n\n{}\n", msg, compile_warnings(allowance_without_splitting, msg_config, lang));
             collected_warnings.push(warning);
         }
         if !collected_warnings.is_empty() {
-            let msg = get_msg(&msg_config, "filter_split_conflict", lang);
+            let msg = get_msg(msg_config, "filter_split_conflict", lang);
             let warnings = format!("{}:\n\n{}", msg, collected_warnings.join("\n"));
             warn!("{warnings}");
             if config.settings.inconsistency_notification {
-                send_mail(config, msg_config, warnings);
+                let _ = send_mail(config, msg_config, warnings);
             }
         }
     } else {
-        let msg = get_msg(&msg_config, "allow_block_conflict", lang);
+        let msg = get_msg(msg_config, "allow_block_conflict", lang);
         let warning = format!("{}:\n  • {}", msg, inconsistent_filter.join("\n  • "));
         warn!("{warning}");
         if config.settings.inconsistency_notification {
-            send_mail(config, msg_config, warning);
+            let _ = send_mail(config, msg_config, warning);
         }
     }
 }
 
-pub fn get_original(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Result<String> {
-    let original: String;
+pub fn get_original(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Result<Vec<String>> {
     let dir = Path::new(&config.settings.dirs.original);
     let lang = &config.settings.lang;
-    
-    match fs::read_dir(dir) {
-        Ok(entries) => {
-            let mut files:Vec<String> = vec![];
-            for entry in entries {
-                match entry {
-                    Ok(file) => {
-                        files.push(file.file_name().into_string().unwrap());
-                    }
-                    Err(err) => {
-                        let msg = get_msg(&msg_config, "reading_dir_entry_failed", lang);
-                        let error_msg = format!("{}: {:?} - {}", msg, dir, err);
-                        error!("{error_msg}");
-                        send_mail(config, msg_config, error_msg);
-                        panic!();
-                    }
-                }
-            }
-            match files.len() {
-                0 => {
-                    let msg = get_msg(&msg_config, "missing_original", lang);
-                    let error_msg = format!("{}: {:?}", msg, dir);
-                    error!("{error_msg}");
-                    send_mail(config, msg_config, error_msg);
-                    panic!();
-                }
-                1 => original = files[0].clone(),
-                _ => {
-                    let msg = get_msg(&msg_config, "more_than_one_original", lang);
-                    let error_msg = format!("{}: {:?}", msg, files);
-                    error!("{error_msg}");
-                    send_mail(config, msg_config, error_msg);
-                    panic!();
-                }
+    let batch = &config.settings.batch;
+
+    let pattern = Regex::new(&batch.pattern).map_err(|err| Error::Config {
+        msg: get_msg(msg_config, "invalid_batch_pattern", lang),
+        path: "settings.batch.pattern".to_string(),
+        detail: err.to_string(),
+    })?;
+
+    let io_error = |key: &str, source: io::Error| Error::Io {
+        msg: get_msg(msg_config, key, lang),
+        path: config.settings.dirs.original.clone(),
+        source,
+    };
+    let mut files: Vec<(String, SystemTime)> = vec![];
+    let entries = fs::read_dir(dir).map_err(|err| io_error("reading_original_dir_failed", err))?;
+    for entry in entries {
+        let file = entry.map_err(|err| io_error("reading_dir_entry_failed", err))?;
+        let file_name = file.file_name().to_string_lossy().to_string();
+        if batch.active {
+            // in batch mode, subdirectories and non-matching files are ignored:
+            let is_file = file.file_type().map(|t| t.is_file()).unwrap_or(false);
+            if !is_file || !pattern.is_match(&file_name) {
+                continue;
             }
         }
-        Err(err) => {
-            let msg = get_msg(&msg_config, "reading_original_dir_failed", lang);
-            let error_msg = format!("{}: {:?} - {}", msg, dir, err);
-            error!("{error_msg}");
-            send_mail(config, msg_config, error_msg);
-            panic!();
+        let modified = file.metadata()
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+        files.push((file_name, modified));
+    }
+
+    match files.len() {
+        0 => {
+            return Err(Error::Original {
+                msg: get_msg(msg_config, "missing_original", lang),
+                dir: config.settings.dirs.original.clone(),
+                detail: String::new(),
+            });
+        }
+        1 => (),
+        _ => {
+            if !batch.active {
+                let names: Vec<&String> = files.iter().map(|(name, _)| name).collect();
+                return Err(Error::Original {
+                    msg: get_msg(msg_config, "more_than_one_original", lang),
+                    dir: config.settings.dirs.original.clone(),
+                    detail: format!("{:?}", names),
+                });
+            }
         }
     }
-    
-    Ok(original)
+
+    match batch.order.as_str() {
+        "mtime" => files.sort_by(|(name1, mtime1), (name2, mtime2)| mtime1.cmp(mtime2).then(name1.cmp(name2))),
+        _ => files.sort_by(|(name1, _), (name2, _)| name1.cmp(name2)),
+    }
+
+    Ok(files.into_iter().map(|(name, _)| name).collect())
 }
 
-pub fn write_file(file_path: &str, contents: &str) -> Result<()> {
-    let mut file = fs::File::create(file_path)?;
-    file.write_all(contents.as_bytes())
+/// Maps each group to the path of its output file, derived from the name of the original file.
+pub fn get_output_paths(config: &Config, original_file: &str) -> HashMap<String, String> {
+    let file_stem = Path::new(Compression::strip(original_file)).file_stem().and_then(|stem| stem.to_str()).unwrap_or(original_file);
+    let file_timestamp: String = Local::now()
+    .format(&config.settings.timeformats.files).to_string();
+    get_groups(config)
+    .into_iter()
+    .map(|group| {
+        let output = config.outputs.get(&group).cloned().unwrap_or_default();
+        let output_path = format!(
+            "{}/{}_{}_{}.{}{}",
+            config.settings.dirs.transformed,
+            group,
+            file_stem,
+            file_timestamp,
+            output.format.extension(),
+            output.compression.extension()
+        );
+        (group, output_path)
+    }).collect()
 }
 
-pub fn write_xml(
-    xml_events: &Vec<Event>,
+pub fn finish_output(
+    mut sink: Box<dyn Sink>,
     output_path: &str,
     msg_config: &HashMap<String, HashMap<String, String>>,
     config: &Config
 ) -> Result<()> {
     let lang = &config.settings.lang;
-    let mut buffer: Vec<u8> = Vec::new();
-    let mut writer: Writer<&mut Vec<u8>> = Writer::new(&mut buffer);
-    for event in xml_events {
-        if let Err(err) = writer.write_event(event.clone()) {
-            let msg = get_msg(&msg_config, "writing_event_failed", lang);
-            error!("{}", format!("{}: {:?} - {}", msg, &event, err));
-        }
-    }
-
-    match std::str::from_utf8(writer.into_inner()) {
-        Ok(revised_xml) => {
-            match write_file(output_path, revised_xml) {
-                Ok(_) => {
-                    let msg = get_msg(&msg_config, "file_written", lang);
-                    info!("{}", format!("{}: {:?}", msg, Path::new(&output_path)));
-                }
-                Err(err) => {
-                    let msg = get_msg(&msg_config, "writing_file_failed", lang);
-                    let error_msg = format!("{}: {}", msg, err);
-                    error!("{error_msg}");
-                    send_mail(config, msg_config, error_msg);
-                }
-            }
+    match sink.finish() {
+        Ok(_) => {
+            let msg = get_msg(msg_config, "file_written", lang);
+            info!("{}: {:?}", msg, Path::new(&output_path));
+            Ok(())
         }
         Err(err) => {
-            let msg = get_msg(&msg_config, "str_from_writer_failed", lang);
-            let error_msg = format!("{}: {}", msg, err);
-            error!("{error_msg}");
-            send_mail(config, msg_config, error_msg);
+            let error = Error::Io {
+                msg: get_msg(msg_config, "writing_file_failed", lang),
+                path: output_path.to_string(),
+                source: err,
+            };
+            error!("{error}");
+            let _ = send_mail(config, msg_config, error.to_string());
+            Err(error)
         }
-    } 
-    Ok(())
+    }
 }
 
-pub fn superordinate(path1: &Vec<String>, path2: &Vec<&str>) -> Result<bool> {
-    let matching = path2.iter().zip(path1).filter(|&(a, b)| a == b);
-    Ok(matching.count() < path2.len())
+/// Moves a file to the history, compressing it according to the `history_compression` setting.
+pub fn archive(
+    file_path: &Path,
+    archive_path: &Path,
+    config: &Config
+) -> io::Result<()> {
+    archive_compressed(file_path, archive_path, config.settings.history_compression).map(|_| ())
 }
 
-pub fn get_config(path_str: &str) -> Config {
-    let config_path: &Path = Path::new(path_str);
-    let config_str = match fs::read_to_string(&config_path) {
-        Ok(s) => s,
-        Err(err) => {
-            error!("Can't read configuration file {:?}: {}", config_path, err);
-            panic!();
+/// Moves a file to the archive path, compressing it on the way unless it is compressed already.
+/// Returns the path of the archived file, which gets the extension of the compression.
+pub fn archive_compressed(file_path: &Path, archive_path: &Path, compression: Compression) -> io::Result<PathBuf> {
+    let name = archive_path.to_string_lossy();
+    if compression == Compression::None || Compression::from_path(&name) != Compression::None {
+        fs::rename(file_path, archive_path)?;
+        return Ok(archive_path.to_path_buf());
+    }
+    let compressed_path = PathBuf::from(format!("{}{}", name, compression.extension()));
+    let mut encoder = Encoder::new(BufWriter::new(File::create(&compressed_path)?), compression)?;
+    let result = io::copy(&mut BufReader::new(File::open(file_path)?), &mut encoder)
+    .and_then(|_| encoder.finish());
+    match result {
+        Ok(_) => {
+            fs::remove_file(file_path)?;
+            Ok(compressed_path)
         }
-    };
-
-    let config: Config = match toml::from_str(&config_str) {
-        Ok(c) =>  c,
         Err(err) => {
-            error!("Unable to parse configuration {:?}: {}", config_path, err);
-            panic!();
+            drop(encoder);
+            let _ = fs::remove_file(&compressed_path);
+            Err(err)
         }
-    };
-    config
+    }
 }
 
-pub fn get_msg_config(path_str: &str) -> HashMap<String, HashMap<String, String>> {
-    let msg_config_path: &Path = Path::new(path_str);
-    let msg_config_str = match fs::read_to_string(&msg_config_path) {
-        Ok(s) => s,
-        Err(err) => {
-            let error_msg = format!("Can't read message configuration file {:?}: {}", msg_config_path, err);
-            error!("{error_msg}");
-            panic!();
+/// Sends an e-mail with the given body to the configured recipients. Failures are logged
+/// and returned, so that callers for which the e-mail is essential can act on them.
+pub fn send_mail(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, body: String) -> Result<()> {
+    match deliver_mail(config, msg_config, body) {
+        Ok(_) => {
+            let msg = get_msg(msg_config, "send_email_successful", &config.settings.lang);
+            info!("{msg}");
+            Ok(())
         }
-    };
-
-    let msg_config: HashMap<String, HashMap<String, String>> = match toml::from_str(&msg_config_str) {
-        Ok(c) =>  c,
         Err(err) => {
-            let error_msg = format!("Unable to parse configuration {:?}: {}", msg_config_path, err);
-            error!("{error_msg}");
-            panic!();
+            error!("{err}");
+            Err(err)
         }
-    };
-    msg_config
-}
-
-pub fn get_msg(msg_config: &HashMap<String, HashMap<String, String>>, msg_key: &str, lang: &String) -> String {
-    match msg_config
-    .get(msg_key)
-    .and_then(|map| map.get(lang)) {
-        Some(msg) => msg.clone().to_string(),
-        None => msg_key.to_string(),
     }
 }
 
-pub fn archive(
-    file_path: &Path,
-    archive_path: &Path
-) -> Result<()>{
-    let result = match fs::rename(file_path, archive_path) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err),
-    };
-    result
-}
-
-pub fn send_mail(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, body: String) {
+fn deliver_mail(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>, body: String) -> Result<()> {
     let email_settings = &config.settings.email.message;
     let mailer_settings = &config.settings.email.mailer;
+    let mail_error = |key: &str, detail: String| Error::Mail {
+        msg: get_msg(msg_config, key, &config.settings.lang),
+        detail,
+    };
 
-    let from: Mailbox;
-    match email_settings.from.parse::<Mailbox>() {
-        Ok(mbox) => from = mbox,
-        Err(err) => {
-            let msg = get_msg(&msg_config, "from_error", &config.settings.lang);
-            error!("{msg}: {err}");
-            return;
-        }
-    }
+    let from: Mailbox = email_settings.from.parse::<Mailbox>()
+    .map_err(|err| mail_error("from_error", err.to_string()))?;
 
-    let reply_to_header: header::ReplyTo;
-    match email_settings.reply_to.join(",").parse::<Mailboxes>() {
-        Ok(mboxes) => reply_to_header = mboxes.into(),
-        Err(err) => {
-            let msg = get_msg(&msg_config, "reply_to_error", &config.settings.lang);
-            error!("{msg}: {err}");
-            return;
-        }
-    }
-    
-    let to_header: header::To;
-    match email_settings.to.join(",").parse::<Mailboxes>() {
-        Ok(mboxes) => to_header = mboxes.into(),
-        Err(err) => {
-            let msg = get_msg(&msg_config, "to_error", &config.settings.lang);
-            error!("{msg}: {err}");
-            return;
-        }
-    }
+    let reply_to_header: header::ReplyTo = email_settings.reply_to.join(",").parse::<Mailboxes>()
+    .map_err(|err| mail_error("reply_to_error", err.to_string()))?
+    .into();
+
+    let to_header: header::To = email_settings.to.join(",").parse::<Mailboxes>()
+    .map_err(|err| mail_error("to_error", err.to_string()))?
+    .into();
 
-    let email: Message;
-    match MessageBuilder::new()
+    let email: Message = MessageBuilder::new()
     .from(from)
     .mailbox(reply_to_header)
     .mailbox(to_header)
     .subject(email_settings.subject.to_string())
     .header(ContentType::TEXT_PLAIN)
-    .body(body) {
-        Ok(message) => email = message,
-        Err(err) => {
-            let msg = get_msg(&msg_config, "message_building_error", &config.settings.lang);
-            error!("{msg}: {err}");
-            return;
-        }
-    }
-    
-    let mailer: SmtpTransport;
-
-    if config.settings.email.mailer.auth {
-        let smtp_user: String;
-        let smtp_pw: String;
-        match env::vars().find(|(k, _)| k == "SMTP_USER") {
-            Some(entry) => {
-                smtp_user = entry.1;
-                if smtp_user.trim().is_empty() {
-                    let msg = get_msg(&msg_config, "missing_smtp_user", &config.settings.lang);
-                    error!("{msg}");
-                    return;
-                }
-                match env::vars().find(|(k, _)| k == "SMTP_PW") {
-                    Some(entry) => {
-                        smtp_pw = entry.1;
-                        if smtp_pw.trim().is_empty() {
-                            let msg = get_msg(&msg_config, "missing_smtp_pw", &config.settings.lang);
-                            error!("{msg}");
-                            return;
-                        }
-                    }
-                    None => {
-                        let msg = get_msg(&msg_config, "missing_smtp_pw", &config.settings.lang);
-                        error!("{msg}");
-                        return;
-                    }
-                }
-            }
-            None => {
-                let msg = get_msg(&msg_config, "missing_smtp_user", &config.settings.lang);
-                error!("{msg}");
-                return;
-            }
-        }
-
-        let creds = Credentials::new(smtp_user, smtp_pw);
+    .body(body)
+    .map_err(|err| mail_error("message_building_error", err.to_string()))?;
 
-        mailer = SmtpTransport::starttls_relay(&mailer_settings.smtp).unwrap()
-        .port(mailer_settings.port)
-        .credentials(creds)
-        .build();
+    let relay = SmtpTransport::starttls_relay(&mailer_settings.smtp)
+    .map_err(|err| mail_error("send_email_failed", err.to_string()))?
+    .port(mailer_settings.port);
 
-    } else {
-        mailer = SmtpTransport::starttls_relay(&mailer_settings.smtp).unwrap()
-        .port(mailer_settings.port)
-        .build();
-    }
-
-    match mailer.send(&email) {
-        Ok(_) => {
-            let msg = get_msg(&msg_config, "send_email_successful", &config.settings.lang);
-            info!("{msg}");
+    let mailer: SmtpTransport = if config.settings.email.mailer.auth {
+        let smtp_user = env::var("SMTP_USER").unwrap_or_default();
+        if smtp_user.trim().is_empty() {
+            return Err(mail_error("missing_smtp_user", "SMTP_USER".to_string()));
         }
-        Err(err) => {
-            let msg = get_msg(&msg_config, "send_email_failed", &config.settings.lang);
-            error!("{msg}: {err}");
+        let smtp_pw = env::var("SMTP_PW").unwrap_or_default();
+        if smtp_pw.trim().is_empty() {
+            return Err(mail_error("missing_smtp_pw", "SMTP_PW".to_string()));
         }
-    }
+        relay.credentials(Credentials::new(smtp_user, smtp_pw)).build()
+    } else {
+        relay.build()
+    };
+
+    mailer.send(&email).map_err(|err| mail_error("send_email_failed", err.to_string()))?;
+    Ok(())
 }
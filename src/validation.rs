@@ -0,0 +1,537 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+use regex::Regex;
+use chrono::format::{self, StrftimeItems};
+use serde::de::{DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::common::get_msg;
+use crate::config::{Config, Settings, Upload};
+use crate::output::{csv_delimiter, output_encoding, Format, Output};
+use crate::transformations::condition::{describe_regex_error, Condition};
+use crate::transformations::filter::{default_date_format, Filter};
+use crate::transformations::split::Split;
+use crate::transformations::transformer::Transformation;
+
+/// A problem found in a configuration file, located by its line and column (both starting at 1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.text)
+    }
+}
+
+/// The keys expected in a part of the configuration; the types of the values are checked
+/// when the configuration is deserialized.
+enum Shape {
+    /// A value (or table) whose keys aren't checked.
+    Value,
+    /// A table with the given keys.
+    Table(&'static [(&'static str, Shape)]),
+    /// A table with arbitrary keys (e. g. paths or group names) whose values have the given shape.
+    Map(&'static Shape),
+    /// An array of tables of the given shape.
+    Array(&'static Shape),
+}
+
+const LISTS: Shape = Shape::Table(&[
+    ("exact", Shape::Map(&Shape::Value)),
+    ("regex", Shape::Map(&Shape::Value)),
+    ("quantifiers", Shape::Map(&Shape::Value)),
+]);
+
+const TRANSFORMATION: Shape = Shape::Table(&[
+    ("target", Shape::Value),
+    ("keep", Shape::Value),
+    ("value", Shape::Value),
+    ("rename", Shape::Value),
+    ("nodes", Shape::Table(&[("insert", Shape::Value), ("append", Shape::Value)])),
+    ("source", Shape::Table(&[
+        ("datafields", Shape::Map(&Shape::Value)),
+        ("literals", Shape::Map(&Shape::Value)),
+    ])),
+    ("preconditions", Shape::Table(&[("missing", Shape::Value), ("existing", Shape::Value)])),
+    ("parameters", Shape::Table(&[("decimal_places", Shape::Value)])),
+]);
+
+const CONFIG: Shape = Shape::Table(&[
+    ("element", Shape::Value),
+    ("namespaces", Shape::Map(&Shape::Value)),
+    ("filter", Shape::Table(&[
+        ("residue", Shape::Value),
+        ("allowlist", LISTS),
+        ("blocklist", LISTS),
+        ("presence", Shape::Table(&[("existing", Shape::Value), ("missing", Shape::Value)])),
+        ("rules", Shape::Map(&Shape::Value)),
+        ("date_format", Shape::Value),
+    ])),
+    ("split", Shape::Table(&[
+        ("declaration", Shape::Value),
+        ("default", Shape::Value),
+        ("grouping", Shape::Map(&Shape::Map(&Shape::Value))),
+        ("matches", Shape::Map(&Shape::Value)),
+        ("missing", Shape::Map(&Shape::Value)),
+        ("existing", Shape::Map(&Shape::Value)),
+    ])),
+    ("outputs", Shape::Map(&Shape::Table(&[
+        ("format", Shape::Value),
+        ("columns", Shape::Value),
+        ("delimiter", Shape::Value),
+        ("compression", Shape::Value),
+        ("encoding", Shape::Value),
+        ("schema", Shape::Value),
+    ]))),
+    ("transformations", Shape::Array(&TRANSFORMATION)),
+    ("uploads", Shape::Array(&Shape::Table(&[
+        ("active", Shape::Value),
+        ("timeout", Shape::Value),
+        ("protocol", Shape::Value),
+        ("server", Shape::Value),
+        ("path", Shape::Value),
+        ("user", Shape::Value),
+        ("key", Shape::Value),
+        ("pubkey", Shape::Value),
+        ("include", Shape::Value),
+        ("exclude", Shape::Value),
+        ("schema", Shape::Value),
+    ]))),
+    ("settings", Shape::Table(&[
+        ("lang", Shape::Value),
+        ("history_size", Shape::Value),
+        ("history_compression", Shape::Value),
+        ("consistency_check", Shape::Value),
+        ("inconsistency_notification", Shape::Value),
+        ("batch", Shape::Table(&[("active", Shape::Value), ("order", Shape::Value), ("pattern", Shape::Value)])),
+        ("watch", Shape::Table(&[("interval", Shape::Value), ("stable_for", Shape::Value), ("marker", Shape::Value)])),
+        ("dirs", Shape::Table(&[("original", Shape::Value), ("transformed", Shape::Value), ("history", Shape::Value)])),
+        ("timeformats", Shape::Table(&[("history_folder", Shape::Value), ("files", Shape::Value)])),
+        ("email", Shape::Table(&[
+            ("mailer", Shape::Table(&[("smtp", Shape::Value), ("port", Shape::Value), ("auth", Shape::Value)])),
+            ("message", Shape::Table(&[
+                ("from", Shape::Value),
+                ("reply_to", Shape::Value),
+                ("to", Shape::Value),
+                ("subject", Shape::Value),
+            ])),
+        ])),
+    ])),
+]);
+
+/// A step on the way to a key or value of the configuration.
+enum Step<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// A parsed configuration file that keeps the position of every key and value.
+struct Document<'a> {
+    source: &'a str,
+    document: ImDocument<&'a str>,
+}
+
+impl Document<'_> {
+    /// Finds the position of the value at the end of the steps, or of the last key on the way
+    /// if the value itself doesn't exist.
+    fn locate(&self, steps: &[Step]) -> Option<Range<usize>> {
+        locate_in(self.document.as_table(), steps, None)
+    }
+}
+
+fn locate_in(table: &dyn TableLike, steps: &[Step], span: Option<Range<usize>>) -> Option<Range<usize>> {
+    let Some((Step::Key(key), rest)) = steps.split_first() else {
+        return span;
+    };
+    let Some((key, item)) = table.get_key_value(key) else {
+        return span;
+    };
+    let span = key.span().or(span);
+    match rest.split_first() {
+        None => item.span().or(span),
+        Some((Step::Index(index), rest)) => {
+            if let Some(table) = item.as_array_of_tables().and_then(|tables| tables.get(*index)) {
+                return locate_in(table, rest, table.span().or(span));
+            }
+            match item.as_array().and_then(|array| array.get(*index)) {
+                Some(value) if rest.is_empty() => value.span().or(span),
+                Some(value) => match value.as_inline_table() {
+                    Some(table) => locate_in(table, rest, value.span().or(span)),
+                    None => value.span().or(span),
+                },
+                None => span,
+            }
+        }
+        Some(_) => match item.as_table_like() {
+            Some(table) => locate_in(table, rest, span),
+            None => span,
+        },
+    }
+}
+
+fn problem_at(source: &str, span: Option<Range<usize>>, text: String) -> Problem {
+    let offset = span.map_or(0, |span| span.start).min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or(before).chars().count() + 1;
+    Problem { line, column, text }
+}
+
+fn describe(path: &str) -> String {
+    if path.is_empty() {
+        "the configuration".to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+impl Document<'_> {
+    fn check_item(&self, item: &Item, shape: &Shape, path: &str, problems: &mut Vec<Problem>) {
+        match shape {
+            Shape::Value => (),
+            Shape::Array(shape) => {
+                if let Some(tables) = item.as_array_of_tables() {
+                    for (i, table) in tables.iter().enumerate() {
+                        self.check_table(table, shape, &format!("{path} #{}", i + 1), problems);
+                    }
+                } else if let Some(array) = item.as_array() {
+                    for (i, value) in array.iter().enumerate() {
+                        if let Some(table) = value.as_inline_table() {
+                            self.check_table(table, shape, &format!("{path} #{}", i + 1), problems);
+                        }
+                    }
+                }
+            }
+            _ => {
+                if let Some(table) = item.as_table_like() {
+                    self.check_table(table, shape, path, problems);
+                }
+            }
+        }
+    }
+
+    fn check_table(&self, table: &dyn TableLike, shape: &Shape, path: &str, problems: &mut Vec<Problem>) {
+        match shape {
+            Shape::Table(keys) => {
+                for (key, item) in table.iter() {
+                    match keys.iter().find(|(name, _)| *name == key) {
+                        Some((_, shape)) => self.check_item(item, shape, &join(path, key), problems),
+                        None => {
+                            let span = table.get_key_value(key).and_then(|(key, _)| key.span());
+                            let expected = keys.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ");
+                            let text = format!("unknown key `{key}` in {} (expected: {expected})", describe(path));
+                            problems.push(problem_at(self.source, span, text));
+                        }
+                    }
+                }
+            }
+            Shape::Map(shape) => {
+                for (key, item) in table.iter() {
+                    self.check_item(item, shape, &join(path, &format!("\"{key}\"")), problems);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Deserializes every top-level key of the document on its own (one for each field of `Config`),
+    /// so that the values of the wrong type are reported in all tables and not only in the first one.
+    fn check_fields(&self, problems: &mut Vec<Problem>) {
+        self.check_field::<String>("element", problems);
+        self.check_field::<HashMap<String, String>>("namespaces", problems);
+        self.check_field::<Filter>("filter", problems);
+        self.check_field::<Split>("split", problems);
+        self.check_field::<HashMap<String, Output>>("outputs", problems);
+        self.check_field::<Vec<Transformation>>("transformations", problems);
+        self.check_field::<Vec<Upload>>("uploads", problems);
+        self.check_field::<Settings>("settings", problems);
+    }
+
+    fn check_field<T: DeserializeOwned>(&self, key: &str, problems: &mut Vec<Problem>) {
+        let field = Field::<T> { key, value: PhantomData };
+        if let Err(err) = field.deserialize(toml_edit::de::Deserializer::from(self.document.clone())) {
+            problems.push(problem_at(self.source, err.span(), err.message().to_string()));
+        }
+    }
+
+    /// Checks the regular expressions, filter rules and transformation expressions, which are compiled
+    /// before the original files are read, and the `decimal_places` of the transformations. Values of
+    /// the wrong type are skipped here, they are reported by `check_fields`.
+    fn check_patterns(&self, problems: &mut Vec<Problem>) {
+        let root = self.document.as_table();
+        for list in ["allowlist", "blocklist"] {
+            let regex_list = lookup(root, &["filter", list, "regex"]).and_then(Item::as_table_like);
+            for (path, patterns) in regex_list.into_iter().flat_map(|regex_list| regex_list.iter()) {
+                for (j, pattern) in patterns.as_array().into_iter().flatten().enumerate() {
+                    let Some(pattern) = pattern.as_str() else { continue };
+                    if let Err(err) = Regex::new(pattern) {
+                        let steps = [Step::Key("filter"), Step::Key(list), Step::Key("regex"), Step::Key(path), Step::Index(j)];
+                        problems.push(self.problem(&steps, format!("invalid regular expression {:?}: {}", pattern, describe_regex_error(&err))));
+                    }
+                }
+            }
+        }
+        let date_format = lookup(root, &["filter", "date_format"]).and_then(Item::as_str)
+        .map_or_else(default_date_format, str::to_string);
+        if StrftimeItems::new(&date_format).any(|item| item == format::Item::Error) {
+            let steps = [Step::Key("filter"), Step::Key("date_format")];
+            problems.push(self.problem(&steps, format!("invalid date format {:?}", date_format)));
+        }
+        let rules = lookup(root, &["filter", "rules"]).and_then(Item::as_table_like);
+        for (name, expression) in rules.into_iter().flat_map(|rules| rules.iter()) {
+            let Some(expression) = expression.as_str() else { continue };
+            if let Err(err) = Condition::parse(expression, &date_format) {
+                let steps = [Step::Key("filter"), Step::Key("rules"), Step::Key(name)];
+                problems.push(self.problem(&steps, format!("invalid filter rule {:?}: {err}", expression)));
+            }
+        }
+        if let Some(pattern) = lookup(root, &["settings", "batch", "pattern"]).and_then(Item::as_str) {
+            if let Err(err) = Regex::new(pattern) {
+                let steps = [Step::Key("settings"), Step::Key("batch"), Step::Key("pattern")];
+                problems.push(self.problem(&steps, format!("invalid regular expression {:?}: {}", pattern, describe_regex_error(&err))));
+            }
+        }
+        for (i, transformation) in tables(root.get("transformations")) {
+            let not_empty = |key| lookup(transformation, &["source", key]).and_then(Item::as_table_like).is_some_and(|table| !table.is_empty());
+            // values without datafields and literals are inserted as string literals and not evaluated:
+            if not_empty("datafields") || not_empty("literals") {
+                if let Some(value) = lookup(transformation, &["value"]).and_then(Item::as_str) {
+                    if let Err(err) = evalexpr::build_operator_tree(value) {
+                        let steps = [Step::Key("transformations"), Step::Index(i), Step::Key("value")];
+                        problems.push(self.problem(&steps, format!("invalid expression {:?}: {err}", value)));
+                    }
+                }
+            }
+            if let Some(num_str) = lookup(transformation, &["parameters", "decimal_places"]).and_then(Item::as_str) {
+                if let Err(err) = num_str.parse::<usize>() {
+                    let steps = [Step::Key("transformations"), Step::Index(i), Step::Key("parameters"), Step::Key("decimal_places")];
+                    problems.push(self.problem(&steps, format!("invalid decimal_places {:?}: {err}", num_str)));
+                }
+            }
+        }
+    }
+
+    fn problem(&self, steps: &[Step], text: String) -> Problem {
+        problem_at(self.source, self.locate(steps), text)
+    }
+}
+
+/// Parses a configuration file and reports every syntax error, unknown key, value of the wrong type
+/// (or missing value), invalid regular expression, filter rule and transformation expression with a syntax error
+/// together with its position.
+pub fn parse_config(source: &str) -> Result<Config, Vec<Problem>> {
+    let document = match ImDocument::parse(source) {
+        Ok(document) => Document { source, document },
+        Err(err) => return Err(vec![problem_at(source, err.span(), err.message().to_string())]),
+    };
+    let mut problems: Vec<Problem> = Vec::new();
+    document.check_table(document.document.as_table(), &CONFIG, "", &mut problems);
+    document.check_fields(&mut problems);
+    document.check_patterns(&mut problems);
+    if problems.is_empty() {
+        // only missing top-level keys are left to be reported here:
+        match toml::from_str::<Config>(source) {
+            Ok(config) => return Ok(config),
+            Err(err) => problems.push(problem_at(source, err.span(), err.message().to_string())),
+        }
+    }
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    Err(problems)
+}
+
+/// Deserializes the value of a single key of a table, ignoring all other keys; `None` if the key is missing.
+struct Field<'a, T> {
+    key: &'a str,
+    value: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned> DeserializeSeed<'de> for Field<'_, T> {
+    type Value = Option<T>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for Field<'_, T> {
+    type Value = Option<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a table")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut value = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.key {
+                value = Some(map.next_value()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// The item at the end of the keys, if all tables on the way exist.
+fn lookup<'d>(table: &'d dyn TableLike, keys: &[&str]) -> Option<&'d Item> {
+    let (last, keys) = keys.split_last()?;
+    let mut table = table;
+    for key in keys {
+        table = table.get(key)?.as_table_like()?;
+    }
+    table.get(last)
+}
+
+/// The tables of an array of tables (or of an array of inline tables) with their indices.
+fn tables(item: Option<&Item>) -> Vec<(usize, &dyn TableLike)> {
+    match item {
+        Some(Item::ArrayOfTables(tables)) => tables.iter().map(|table| table as &dyn TableLike).enumerate().collect(),
+        Some(item) => item.as_array().into_iter().flatten().enumerate()
+        .filter_map(|(i, value)| value.as_inline_table().map(|table| (i, table as &dyn TableLike)))
+        .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn inside(path: &str, element: &str) -> bool {
+    path == element || path.strip_prefix(element).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Checks the settings of a parsed configuration that can't be checked when it is parsed, e. g. paths
+/// outside the split element or missing directories, and reports every problem with its position.
+pub fn check_config(
+    source: &str,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>,
+    lang: &String
+) -> Vec<Problem> {
+    let document = match ImDocument::parse(source) {
+        Ok(document) => Document { source, document },
+        Err(err) => return vec![problem_at(source, err.span(), err.message().to_string())],
+    };
+    let mut problems: Vec<Problem> = Vec::new();
+    let outside = get_msg(msg_config, "outside_element", lang);
+
+    let lists = [
+        ("allowlist", &config.filter.allowlist.exact, &config.filter.allowlist.regex, &config.filter.allowlist.quantifiers),
+        ("blocklist", &config.filter.blocklist.exact, &config.filter.blocklist.regex, &config.filter.blocklist.quantifiers),
+    ];
+    for (list, exact, regex, quantifiers) in lists {
+        for path in quantifiers.keys() {
+            if !exact.contains_key(path) && !regex.contains_key(path) {
+                let msg = get_msg(msg_config, "quantifier_without_entry", lang);
+                let steps = [Step::Key("filter"), Step::Key(list), Step::Key("quantifiers"), Step::Key(path)];
+                problems.push(document.problem(&steps, format!("filter.{list}.quantifiers: {msg} - {path}")));
+            }
+        }
+    }
+    for path in config.split.matches.keys() {
+        if !config.split.grouping.contains_key(path) {
+            let msg = get_msg(msg_config, "matches_without_grouping", lang);
+            let steps = [Step::Key("split"), Step::Key("matches"), Step::Key(path)];
+            problems.push(document.problem(&steps, format!("split.matches: {msg} - {path}")));
+        }
+    }
+    for (kind, paths) in [("existing", &config.filter.presence.existing), ("missing", &config.filter.presence.missing)] {
+        for (j, path) in paths.iter().enumerate() {
+            if !inside(path, &config.element) {
+                let steps = [Step::Key("filter"), Step::Key("presence"), Step::Key(kind), Step::Index(j)];
+                problems.push(document.problem(&steps, format!("filter.presence.{kind}: {outside} ({}) - {path}", config.element)));
+            }
+        }
+    }
+    for (kind, labels) in [("missing", &config.split.missing), ("existing", &config.split.existing)] {
+        for path in labels.keys() {
+            if !inside(path, &config.element) {
+                let steps = [Step::Key("split"), Step::Key(kind), Step::Key(path)];
+                problems.push(document.problem(&steps, format!("split.{kind}: {outside} ({}) - {path}", config.element)));
+            }
+        }
+    }
+    for (name, expression) in &config.filter.rules {
+        if let Ok(condition) = Condition::parse(expression, &config.filter.date_format) {
+            for path in condition.paths() {
+                if !inside(path, &config.element) {
+                    let steps = [Step::Key("filter"), Step::Key("rules"), Step::Key(name)];
+                    problems.push(document.problem(&steps, format!("filter.rules.{name}: {outside} ({}) - {path}", config.element)));
+                }
+            }
+        }
+    }
+
+    for (i, transformation) in config.transformations.iter().enumerate() {
+        let rule = format!("{} #{} ({})", get_msg(msg_config, "transformation", lang), i + 1, transformation.target);
+        let step = |key| [Step::Key("transformations"), Step::Index(i), Step::Key(key)];
+        let source = &transformation.source;
+        if !inside(&transformation.target, &config.element) {
+            problems.push(document.problem(&step("target"), format!("{rule}: {outside} ({}) - {}", config.element, transformation.target)));
+        }
+        for (name, path) in &source.datafields {
+            if !inside(path, &config.element) {
+                let steps = [Step::Key("transformations"), Step::Index(i), Step::Key("source"), Step::Key("datafields"), Step::Key(name)];
+                problems.push(document.problem(&steps, format!("{rule}: {outside} ({}) - {name} = {path}", config.element)));
+            }
+        }
+    }
+
+    for (group, output) in &config.outputs {
+        let step = |key| [Step::Key("outputs"), Step::Key(group), Step::Key(key)];
+        if let Err(err) = output_encoding(&output.encoding) {
+            let msg = get_msg(msg_config, "invalid_output_encoding", lang);
+            problems.push(document.problem(&step("encoding"), format!("{msg}: {group} - {err}")));
+        }
+        if let Err(err) = csv_delimiter(output.delimiter) {
+            let msg = get_msg(msg_config, "invalid_csv_delimiter", lang);
+            problems.push(document.problem(&step("delimiter"), format!("{msg}: {group} - {err}")));
+        }
+        if !output.schema.is_empty() {
+            if output.format != Format::Xml {
+                let msg = get_msg(msg_config, "schema_requires_xml", lang);
+                problems.push(document.problem(&step("schema"), format!("{msg}: {group} - {}", output.schema)));
+            }
+            if !Path::new(&output.schema).is_file() {
+                let msg = get_msg(msg_config, "missing_schema", lang);
+                problems.push(document.problem(&step("schema"), format!("{msg}: {group} - {}", output.schema)));
+            }
+        }
+    }
+    for (i, upload) in config.uploads.iter().enumerate() {
+        if !upload.schema.is_empty() && !Path::new(&upload.schema).is_file() {
+            let msg = get_msg(msg_config, "missing_schema", lang);
+            let steps = [Step::Key("uploads"), Step::Index(i), Step::Key("schema")];
+            problems.push(document.problem(&steps, format!("{msg}: {} - {}", upload.server, upload.schema)));
+        }
+    }
+
+    let dirs = [
+        ("original", &config.settings.dirs.original),
+        ("transformed", &config.settings.dirs.transformed),
+        ("history", &config.settings.dirs.history),
+    ];
+    for (key, dir) in dirs {
+        if !Path::new(dir).is_dir() {
+            let msg = get_msg(msg_config, "missing_dir", lang);
+            let steps = [Step::Key("settings"), Step::Key("dirs"), Step::Key(key)];
+            problems.push(document.problem(&steps, format!("{msg}: {dir}")));
+        }
+    }
+
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    problems
+}
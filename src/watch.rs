@@ -0,0 +1,290 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, create_dir_all, remove_file};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use chrono::Local;
+use log::{error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use regex::Regex;
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use xtract::config::*;
+use xtract::pipeline::Pipeline;
+use crate::process::*;
+use crate::utils::*;
+use xtract::error::{Error, Result};
+
+/// Size and modification time of a file in the original directory,
+/// together with the moment this state was first observed.
+struct Observation {
+    size: u64,
+    modified: SystemTime,
+    since: Instant,
+}
+
+/// What has been observed in the original directory while watching.
+#[derive(Default)]
+struct Observations {
+    /// The state of each file that isn't complete yet.
+    files: HashMap<String, Observation>,
+    /// The modification time of each file whose processing failed.
+    failed: HashMap<String, SystemTime>,
+}
+
+/// Watches the original directory and runs the whole pipeline on every new file as soon as it is
+/// complete, i. e. as soon as its marker file exists or, without marker, as soon as its size and
+/// modification time haven't changed for the configured period. Runs until SIGTERM or SIGINT.
+pub fn watch(config: &Config, msg_config: &HashMap<String, HashMap<String, String>>) -> Result<()> {
+    let lang = &config.settings.lang;
+    let settings = &config.settings.watch;
+    let dir = Path::new(&config.settings.dirs.original);
+
+    let terminate = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        if let Err(err) = signal_hook::flag::register(signal, Arc::clone(&terminate)) {
+            let msg = get_msg(msg_config, "signal_registration_failed", lang);
+            error!("{msg}: {signal} - {err}");
+        }
+    }
+
+    let pattern = match Regex::new(&config.settings.batch.pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            let error = Error::Config {
+                msg: get_msg(msg_config, "invalid_batch_pattern", lang),
+                path: "settings.batch.pattern".to_string(),
+                detail: err.to_string(),
+            };
+            error!("{error}");
+            let _ = send_mail(config, msg_config, error.to_string());
+            return Err(error);
+        }
+    };
+
+    // The rules are compiled once for all files processed while watching:
+    let pipeline = Pipeline::new(config, msg_config).inspect_err(|err| {
+        error!("{err}");
+        let _ = send_mail(config, msg_config, err.to_string());
+    })?;
+
+    // The watcher (inotify on Linux) only serves to wake up the loop as soon as something happens
+    // in the original directory; if it can't be set up, the directory is polled at the configured interval:
+    let (tx, rx) = channel();
+    let watcher = match notify::recommended_watcher(tx) {
+        Ok(mut watcher) => match watcher.watch(dir, RecursiveMode::NonRecursive) {
+            Ok(_) => Some(watcher),
+            Err(err) => {
+                let msg = get_msg(msg_config, "watcher_failed", lang);
+                warn!("{msg}: {:?} - {err}", dir);
+                None
+            }
+        },
+        Err(err) => {
+            let msg = get_msg(msg_config, "watcher_failed", lang);
+            warn!("{msg}: {:?} - {err}", dir);
+            None
+        }
+    };
+
+    let msg = get_msg(msg_config, "watch_started", lang);
+    info!("{msg}: {:?}", dir);
+
+    let interval = Duration::from_secs(settings.interval.max(1));
+    let mut observations = Observations::default();
+
+    while !terminate.load(Ordering::Relaxed) {
+        match &watcher {
+            Some(_) => {
+                let _ = rx.recv_timeout(interval);
+                while rx.try_recv().is_ok() {}
+            }
+            None => thread::sleep(interval),
+        }
+        for (original_file, modified) in get_ready(dir, &pattern, settings, &mut observations, Instant::now(), config, msg_config) {
+            if terminate.load(Ordering::Relaxed) {
+                break;
+            }
+            observations.files.remove(&original_file);
+            if process_ready(&original_file, &pipeline, config, msg_config) {
+                observations.failed.remove(&original_file);
+                if !settings.marker.is_empty() {
+                    let _ = remove_file(dir.join(format!("{}{}", original_file, settings.marker)));
+                }
+            } else {
+                // a failed file is left in the original directory and only retried once it has been modified:
+                observations.failed.insert(original_file, modified);
+            }
+        }
+    }
+
+    let msg = get_msg(msg_config, "watch_stopped", lang);
+    info!("{msg}");
+    Ok(())
+}
+
+/// Lists the files of the original directory that are complete at the given moment (see `watch`),
+/// in the order of their modification time; files that failed are skipped until they are modified.
+fn get_ready(
+    dir: &Path,
+    pattern: &Regex,
+    settings: &Watch,
+    observations: &mut Observations,
+    now: Instant,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> Vec<(String, SystemTime)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            let msg = get_msg(msg_config, "reading_original_dir_failed", &config.settings.lang);
+            error!("{}: {:?} - {}", msg, dir, err);
+            return Vec::new();
+        }
+    };
+
+    let stable_for = Duration::from_secs(settings.stable_for);
+    let mut present: HashSet<String> = HashSet::new();
+    let mut ready: Vec<(String, SystemTime)> = Vec::new();
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !settings.marker.is_empty() && name.ends_with(&settings.marker) {
+            continue;
+        }
+        if !pattern.is_match(&name) {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        let size = metadata.len();
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if observations.failed.get(&name) == Some(&modified) {
+            continue;
+        }
+        present.insert(name.clone());
+
+        if !settings.marker.is_empty() {
+            if dir.join(format!("{}{}", name, settings.marker)).exists() {
+                ready.push((name, modified));
+            }
+            continue;
+        }
+
+        let observation = observations.files.entry(name.clone()).or_insert(Observation { size, modified, since: now });
+        if observation.size != size || observation.modified != modified {
+            *observation = Observation { size, modified, since: now };
+        } else if now.duration_since(observation.since) >= stable_for {
+            ready.push((name, modified));
+        }
+    }
+    observations.files.retain(|name, _| present.contains(name));
+
+    ready.sort_by(|(name1, mtime1), (name2, mtime2)| mtime1.cmp(mtime2).then(name1.cmp(name2)));
+    ready
+}
+
+fn process_ready(
+    original_file: &String,
+    pipeline: &Pipeline,
+    config: &Config,
+    msg_config: &HashMap<String, HashMap<String, String>>
+) -> bool {
+    let lang = &config.settings.lang;
+    let hist_dir = Path::new(&config.settings.dirs.history);
+    let time_format = &config.settings.timeformats.history_folder;
+    let timestamp: String = Local::now().format(time_format).to_string();
+
+    check_history(hist_dir, &config.settings.history_size, time_format, &timestamp, msg_config, lang);
+
+    let current_history = &hist_dir.join(&timestamp);
+    if let Err(err) = create_dir_all(current_history) {
+        let msg = get_msg(msg_config, "history_creation_failed", lang);
+        let error_msg = format!("{msg}: {timestamp} - {err}");
+        error!("{error_msg}");
+        let _ = send_mail(config, msg_config, error_msg);
+        return false;
+    }
+
+    let msg = get_msg(msg_config, "processing_original", lang);
+    info!("{msg}: {original_file}");
+    let path_to_original = Path::new(&config.settings.dirs.original).join(original_file);
+    let outcome = process_original(&path_to_original, true, current_history, pipeline, config, msg_config);
+    let successful = outcome.error.is_none();
+    let _ = send_report(&[outcome], config, msg_config);
+    successful
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn setup(name: &str, marker: &str) -> (PathBuf, Config, Watch) {
+        let dir = std::env::temp_dir().join(format!("xtract-watch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let config = get_config(concat!(env!("CARGO_MANIFEST_DIR"), "/config/config.toml")).unwrap();
+        let settings = Watch { interval: 1, stable_for: 10, marker: marker.to_string() };
+        (dir, config, settings)
+    }
+
+    fn ready(dir: &Path, config: &Config, settings: &Watch, observations: &mut Observations, now: Instant) -> Vec<String> {
+        let pattern = Regex::new(&config.settings.batch.pattern).unwrap();
+        get_ready(dir, &pattern, settings, observations, now, config, &HashMap::new())
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    #[test]
+    fn files_are_ready_once_stable() {
+        let (dir, config, settings) = setup("stable", "");
+        let mut observations = Observations::default();
+        let start = Instant::now();
+        fs::write(dir.join("a.xml"), "<payments>").unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        assert!(ready(&dir, &config, &settings, &mut observations, start).is_empty());
+        assert!(ready(&dir, &config, &settings, &mut observations, start + Duration::from_secs(9)).is_empty());
+        // a file that is still being written starts over:
+        fs::write(dir.join("a.xml"), "<payments></payments>").unwrap();
+        assert!(ready(&dir, &config, &settings, &mut observations, start + Duration::from_secs(10)).is_empty());
+        assert!(ready(&dir, &config, &settings, &mut observations, start + Duration::from_secs(19)).is_empty());
+        assert_eq!(ready(&dir, &config, &settings, &mut observations, start + Duration::from_secs(20)), ["a.xml"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_ready_with_their_marker() {
+        let (dir, config, settings) = setup("marker", ".done");
+        let mut observations = Observations::default();
+        let start = Instant::now();
+        fs::write(dir.join("a.xml"), "<payments></payments>").unwrap();
+        fs::write(dir.join("b.xml"), "<payments></payments>").unwrap();
+        assert!(ready(&dir, &config, &settings, &mut observations, start + Duration::from_secs(60)).is_empty());
+        fs::write(dir.join("b.xml.done"), "").unwrap();
+        assert_eq!(ready(&dir, &config, &settings, &mut observations, start), ["b.xml"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_files_are_retried_once_modified() {
+        let (dir, config, settings) = setup("failed", ".done");
+        let mut observations = Observations::default();
+        let start = Instant::now();
+        fs::write(dir.join("a.xml"), "<payments></invoices>").unwrap();
+        fs::write(dir.join("a.xml.done"), "").unwrap();
+        let modified = fs::metadata(dir.join("a.xml")).unwrap().modified().unwrap();
+        observations.failed.insert("a.xml".to_string(), modified);
+        assert!(ready(&dir, &config, &settings, &mut observations, start).is_empty());
+        let file = fs::File::options().write(true).open(dir.join("a.xml")).unwrap();
+        file.set_modified(modified + Duration::from_secs(1)).unwrap();
+        assert_eq!(ready(&dir, &config, &settings, &mut observations, start), ["a.xml"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,580 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use xtract::error::{EXIT_CONFIG, EXIT_EVALUATION, EXIT_IO, EXIT_ORIGINAL, EXIT_SCHEMA, EXIT_XML};
+
+/// A small original file matching the split element of the example configuration.
+const INPUT: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<payments><invoices><invoice><invoice_number>INV-1</invoice_number></invoice></invoices></payments>
+";
+
+/// The batch settings of the example configuration.
+const BATCH: &str = "[settings.batch] # if active, all files in the original folder matching the pattern are processed
+active = false";
+
+/// Creates an empty working directory with the local directories of the example configuration
+/// and an input file (`input.xml`).
+fn workspace(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("xtract-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for sub in ["original_xml", "revised_files", "history"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("config/config.toml"), dir.join("config.toml")).unwrap();
+    fs::write(dir.join("input.xml"), INPUT).unwrap();
+    // no uploads, and e-mail reports fail at once instead of waiting for a server:
+    configure(&dir, &[
+        ("[[uploads]]\nactive = true", "[[uploads]]\nactive = false"),
+        ("smtp = \"smtp.example.com\"", "smtp = \"localhost\""),
+        ("port = 25", "port = 1"),
+    ]);
+    dir
+}
+
+/// Replaces settings in the configuration of the working directory.
+fn configure(dir: &Path, replacements: &[(&str, &str)]) {
+    let path = dir.join("config.toml");
+    let mut config = fs::read_to_string(&path).unwrap();
+    for (from, to) in replacements {
+        assert!(config.contains(from), "{from}");
+        config = config.replacen(from, to, 1);
+    }
+    fs::write(&path, config).unwrap();
+}
+
+/// Enables batch mode in the configuration of the working directory.
+fn batch(dir: &Path, order: &str) {
+    configure(dir, &[
+        (BATCH, &BATCH.replace("active = false", "active = true")),
+        ("order = \"mtime\"", &format!("order = \"{order}\"")),
+    ]);
+}
+
+/// Writes an original file with the given name (and by default the content of `INPUT`),
+/// modified the given number of days after the epoch.
+fn original(dir: &Path, name: &str, content: Option<&str>, days: u64) {
+    let path = dir.join("original_xml").join(name);
+    fs::write(&path, content.unwrap_or(INPUT)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400);
+    File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+}
+
+/// The log of a run (written to the console without a log configuration).
+fn log(output: &Output) -> String {
+    format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
+}
+
+/// The order in which the given original files were processed according to the log.
+fn processed(output: &Output, names: &[&str]) -> Vec<String> {
+    let log = log(output);
+    let mut found: Vec<(usize, String)> = names.iter()
+    .filter_map(|name| log.find(&format!(": {name}\n")).map(|position| (position, name.to_string())))
+    .collect();
+    found.sort();
+    found.into_iter().map(|(_, name)| name).collect()
+}
+
+/// The message configuration of the repository.
+fn messages() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("config/message.toml")
+}
+
+/// A command running xtract in the working directory, independent of the environment of the tests.
+fn command(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_xtract"));
+    command.current_dir(dir).env_remove("CONFIG").env_remove("MSG_CONFIG").env_remove("LOG4RS");
+    command
+}
+
+/// Runs xtract in the working directory with its configuration and the given arguments.
+fn xtract(dir: &Path, args: &[&str]) -> Output {
+    command(dir)
+    .arg("--config").arg("config.toml")
+    .arg("--messages").arg(messages())
+    .args(args)
+    .output()
+    .unwrap()
+}
+
+/// The names of the files in the history folders of the working directory.
+fn history(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir.join("history")).unwrap()
+    .flat_map(|folder| fs::read_dir(folder.unwrap().path()).unwrap())
+    .map(|file| file.unwrap().file_name().to_string_lossy().to_string())
+    .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn batch_processes_files_by_mtime() {
+    let dir = workspace("batch-mtime");
+    batch(&dir, "mtime");
+    original(&dir, "b.xml", None, 1);
+    original(&dir, "c.xml", None, 2);
+    original(&dir, "a.xml", None, 3);
+    original(&dir, "notes.txt", Some("not matching the pattern"), 0);
+    let output = xtract(&dir, &[]);
+    assert!(output.status.success(), "{}", log(&output));
+    assert_eq!(processed(&output, &["a.xml", "b.xml", "c.xml", "notes.txt"]), ["b.xml", "c.xml", "a.xml"]);
+    let archived = history(&dir);
+    for stem in ["a", "b", "c"] {
+        assert!(archived.contains(&format!("{stem}.xml")), "{archived:?}");
+        assert!(archived.iter().any(|name| name.starts_with(&format!("DEFAULT_{stem}_"))), "{archived:?}");
+    }
+    assert!(dir.join("original_xml/notes.txt").is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batch_processes_files_by_name() {
+    let dir = workspace("batch-name");
+    batch(&dir, "name");
+    original(&dir, "b.xml", None, 1);
+    original(&dir, "c.xml", None, 2);
+    original(&dir, "a.xml", None, 3);
+    let output = xtract(&dir, &[]);
+    assert!(output.status.success(), "{}", log(&output));
+    assert_eq!(processed(&output, &["a.xml", "b.xml", "c.xml"]), ["a.xml", "b.xml", "c.xml"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batch_continues_after_a_failing_file() {
+    let dir = workspace("batch-failing");
+    batch(&dir, "name");
+    original(&dir, "a.xml", Some("<payments></invoices>"), 1);
+    original(&dir, "b.xml", None, 2);
+    let output = xtract(&dir, &[]);
+    assert_eq!(output.status.code(), Some(EXIT_XML), "{}", log(&output));
+    // the failing file stays in the original folder, the following one is processed:
+    assert!(dir.join("original_xml/a.xml").is_file());
+    assert!(!dir.join("original_xml/b.xml").exists());
+    let archived = history(&dir);
+    assert!(archived.contains(&"b.xml".to_string()), "{archived:?}");
+    assert!(!archived.iter().any(|name| name.starts_with("DEFAULT_a_")), "{archived:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn watch_processes_marked_files_until_terminated() {
+    let dir = workspace("watch");
+    configure(&dir, &[("interval = 5", "interval = 1"), ("marker = \"\"", "marker = \".done\"")]);
+    let mut child = command(&dir)
+    .arg("--config").arg("config.toml")
+    .arg("--messages").arg(messages())
+    .arg("watch")
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .unwrap();
+    original(&dir, "a.xml", None, 1);
+    File::create(dir.join("original_xml/a.xml.done")).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while !history(&dir).contains(&"a.xml".to_string()) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+    let terminated = Command::new("kill").arg("-TERM").arg(child.id().to_string()).status().unwrap();
+    assert!(terminated.success());
+    let status = child.wait().unwrap();
+    let archived = history(&dir);
+    assert!(archived.contains(&"a.xml".to_string()), "{archived:?}");
+    assert!(archived.iter().any(|name| name.starts_with("DEFAULT_a_")), "{archived:?}");
+    assert!(!dir.join("original_xml/a.xml.done").exists());
+    assert!(status.success(), "{status:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn options_override_the_environment() {
+    let dir = workspace("options");
+    let output = command(&dir)
+    .env("CONFIG", "missing.toml")
+    .env("MSG_CONFIG", "missing.toml")
+    .arg("--config").arg("config.toml")
+    .arg("--messages").arg(messages())
+    .args(["--input", "input.xml"])
+    .output()
+    .unwrap();
+    assert!(output.status.success(), "{}", log(&output));
+    // the messages are taken from the message configuration given by the option:
+    assert!(log(&output).contains("Original-Datei wird verarbeitet: input.xml"), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn environment_is_read_from_an_optional_env_file() {
+    let dir = workspace("env");
+    let output = command(&dir).args(["--input", "input.xml", "dry-run"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_CONFIG), "{}", log(&output));
+    fs::write(dir.join(".env"), format!("CONFIG=config.toml\nMSG_CONFIG={}\n", messages().display())).unwrap();
+    let output = command(&dir).args(["--input", "input.xml", "dry-run"]).output().unwrap();
+    assert!(output.status.success(), "{}", log(&output));
+    assert!(log(&output).contains("Original-Datei wird verarbeitet: input.xml"), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn log_config_option_sets_the_log_configuration() {
+    let dir = workspace("log-config");
+    fs::write(dir.join("log4rs.yml"), "appenders:\n  file:\n    kind: file\n    path: xtract.log\nroot:\n  level: info\n  appenders:\n    - file\n").unwrap();
+    let output = xtract(&dir, &["--log-config", "log4rs.yml", "--input", "input.xml", "dry-run"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let logged = fs::read_to_string(dir.join("xtract.log")).unwrap();
+    assert!(logged.contains("Original-Datei wird verarbeitet: input.xml"), "{logged}");
+    assert!(!log(&output).contains("Original-Datei wird verarbeitet"), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn input_is_read_from_stdin() {
+    let dir = workspace("stdin");
+    let mut child = command(&dir)
+    .arg("--config").arg("config.toml")
+    .arg("--messages").arg(messages())
+    .args(["--input", "-"])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(INPUT.as_bytes()).unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", log(&output));
+    let archived = history(&dir);
+    assert!(archived.iter().any(|name| name.starts_with("stdin_") && name.ends_with(".xml")), "{archived:?}");
+    assert!(archived.iter().any(|name| name.starts_with("DEFAULT_stdin_")), "{archived:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_configuration_fails() {
+    let dir = workspace("missing-config");
+    let output = command(&dir).args(["--config", "missing.toml", "--input", "input.xml"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_CONFIG), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dry_run_reports_without_side_effects() {
+    let dir = workspace("dry-run");
+    // the upload of the example configuration is only reported:
+    configure(&dir, &[("[[uploads]]\nactive = false", "[[uploads]]\nactive = true")]);
+    original(&dir, "input.xml", Some("<?xml version=\"1.0\"?>
+<payments><invoices>
+<invoice><invoice_number>INV-1</invoice_number><owner_entity><orgId>3210705901456789</orgId></owner_entity>
+<vat_info><vat_percentage>8.0</vat_percentage></vat_info></invoice>
+<invoice><invoice_number>INV-2</invoice_number><payment_method>BLOCKEDMETHOD</payment_method></invoice>
+<invoice currency=\"CHF\"><invoice_number>INV-3</invoice_number></invoice>
+</invoices></payments>
+"), 1);
+    let output = xtract(&dir, &["dry-run"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let report = String::from_utf8_lossy(&output.stdout);
+    for expected in [
+        "Testlauf: input.xml",
+        "  • LIB001: 1 ➔ LIB001_input_",
+        ".xml ➔ sftp://dataservice.example.com/data_upload/\n",
+        "  • RESIDUE: 1 ➔ RESIDUE_input_",
+        "  • DEFAULT: 1 ➔ DEFAULT_input_",
+        "  • #2: blocklist.exact: payments/invoices/invoice/payment_method = \"BLOCKEDMETHOD\"\n",
+        "  • 1. payments/invoices/invoice/vat_info/vat_percentage: 1\n",
+        "  • 10. payments/invoices/invoice/@currency: 2\n",
+    ] {
+        assert!(report.contains(expected), "{expected}\n{report}");
+    }
+    assert!(report.contains(".xml ➔ kein Upload\n"), "{report}");
+    // nothing is written, uploaded or archived:
+    assert!(dir.join("original_xml/input.xml").is_file());
+    assert_eq!(fs::read_dir(dir.join("revised_files")).unwrap().count(), 0);
+    assert_eq!(fs::read_dir(dir.join("history")).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn explain_traces_the_selected_split_elements() {
+    let dir = workspace("explain");
+    fs::write(dir.join("input.xml"), "<?xml version=\"1.0\"?>
+<payments><invoices>
+<invoice><invoice_owner>Happy Owner</invoice_owner><vendor_code>VEND-54321</vendor_code>
+<vat_info><vat_percentage>8.0</vat_percentage></vat_info><invoice_lines>
+<line><quantity>1</quantity><price>108.10</price><pol_info><sensitive_data>secret</sensitive_data></pol_info></line>
+<line><price>54.05</price></line>
+</invoice_lines></invoice>
+<invoice status=\"cancelled\"><payment_method>BLOCKEDMETHOD</payment_method></invoice>
+<invoice><invoice_number>INV-3</invoice_number></invoice>
+</invoices></payments>
+").unwrap();
+    let output = xtract(&dir, &["--input", "input.xml", "explain", "--index", "1"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let trace = log(&output);
+    for expected in [
+        "Split-Element #1 ➔ LIB001:\n  • group: DEFAULT\n",
+        "  • grouping: payments/invoices/invoice/invoice_owner = \"Happy Owner\" ➔ LIB001\n",
+        "  • allowlist.exact: payments/invoices/invoice/vendor_code = \"VEND-54321\" ~ \"VEND-54321\" ➔ matched\n",
+        "  • allowlist.regex: payments/invoices/invoice/vendor_code = \"VEND-54321\" ~ \"^(MATCH-)?PATTERN\" ➔ failed\n",
+        "  • transformation 1 (payments/invoices/invoice/vat_info/vat_percentage): preconditions held - \"8.0\" ➔ \"8.1\"\n",
+        "  • transformation 2 (payments/invoices/invoice/invoice_lines/line/price): preconditions held (existing: [payments/invoices/invoice/invoice_lines/line/quantity ✔]) - append net_price = \"100.00\"\n",
+        "  • transformation 2 (payments/invoices/invoice/invoice_lines/line/price): preconditions failed (existing: [payments/invoices/invoice/invoice_lines/line/quantity ✘]) - net_price not appended\n",
+        "  • transformation 5 (payments/invoices/invoice/invoice_lines/line/pol_info/sensitive_data): removed\n",
+    ] {
+        assert!(trace.contains(expected), "{expected}\n{trace}");
+    }
+    assert!(!trace.contains("Split-Element #2"), "{trace}");
+
+    let output = xtract(&dir, &["--input", "input.xml", "explain", "--key", "payments/invoices/invoice/@status=cancelled"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let trace = log(&output);
+    assert!(trace.contains("Split-Element #2 ➔ RESIDUE:\n"), "{trace}");
+    assert!(trace.contains(
+        "  • blocklist.exact: payments/invoices/invoice/payment_method = \"BLOCKEDMETHOD\" ~ \"BLOCKEDMETHOD\" ➔ matched\n"
+    ), "{trace}");
+    assert!(!trace.contains("Split-Element #1") && !trace.contains("Split-Element #3"), "{trace}");
+    assert_eq!(fs::read_dir(dir.join("revised_files")).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn datafields_may_follow_their_targets() {
+    let dir = workspace("lookahead");
+    fs::write(dir.join("input.xml"), "<?xml version=\"1.0\"?>
+<payments><invoices><invoice><invoice_lines>
+<line><price>108.10</price></line>
+</invoice_lines><vat_info><vat_percentage>8.0</vat_percentage></vat_info></invoice></invoices></payments>
+").unwrap();
+    let output = xtract(&dir, &["--input", "input.xml", "explain", "--index", "1"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let trace = log(&output);
+    // the VAT rate comes after the price, but is used to compute its net price:
+    assert!(trace.contains(
+        "  • transformation 3 (payments/invoices/invoice/invoice_lines/line/price): preconditions held (missing: [payments/invoices/invoice/invoice_lines/line/quantity ✔]) - append net_price = \"100.00\"\n"
+    ), "{trace}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn groups_are_written_in_their_formats() {
+    let dir = workspace("formats");
+    configure(&dir, &[(
+        "[outputs]\n",
+        "[outputs]\n[outputs.DEFAULT]\nformat = \"json\"\n\
+        [outputs.RESIDUE]\nformat = \"csv\"\ncolumns = [\"@status\", \"invoice_number\", \"payment_method\"]\ndelimiter = \";\"\n"
+    )]);
+    fs::write(dir.join("input.xml"), "<?xml version=\"1.0\"?>
+<payments><invoices>
+<invoice currency=\"CHF\"><invoice_number>INV-1</invoice_number></invoice>
+<invoice status=\"cancelled\" currency=\"CHF\"><invoice_number>INV-2</invoice_number><payment_method>BLOCKEDMETHOD</payment_method></invoice>
+</invoices></payments>
+").unwrap();
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let archived = history(&dir);
+    let read = |prefix: &str, extension: &str| {
+        let name = archived.iter().find(|name| name.starts_with(prefix) && name.ends_with(extension)).unwrap();
+        read_archived(&dir, name)
+    };
+    assert_eq!(read("DEFAULT_input_", ".json").trim(), "[\n{\"@currency\":\"CHF\",\"invoice_number\":\"INV-1\"}\n]");
+    assert_eq!(read("RESIDUE_input_", ".csv"), "@status;invoice_number;payment_method\ncancelled;INV-2;BLOCKEDMETHOD\n");
+    assert!(archived.iter().any(|name| name.starts_with("LIB001_input_") && name.ends_with(".xml")), "{archived:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Reads a file of the history, decompressing it if necessary.
+fn read_archived(dir: &Path, name: &str) -> String {
+    let folder = fs::read_dir(dir.join("history")).unwrap().next().unwrap().unwrap().path();
+    let file = File::open(folder.join(name)).unwrap();
+    let mut content = String::new();
+    if name.ends_with(".gz") {
+        MultiGzDecoder::new(file).read_to_string(&mut content).unwrap();
+    } else if name.ends_with(".zst") {
+        content = String::from_utf8(zstd::stream::decode_all(file).unwrap()).unwrap();
+    } else {
+        content = fs::read_to_string(folder.join(name)).unwrap();
+    }
+    content
+}
+
+#[test]
+fn compressed_input_output_and_history() {
+    let dir = workspace("compression");
+    configure(&dir, &[
+        ("history_compression = \"none\"", "history_compression = \"zstd\""),
+        ("[outputs]\n", "[outputs]\n[outputs.DEFAULT]\ncompression = \"gzip\"\n"),
+    ]);
+    let mut encoder = GzEncoder::new(File::create(dir.join("original_xml/input.xml.gz")).unwrap(), flate2::Compression::default());
+    encoder.write_all(INPUT.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+
+    let output = xtract(&dir, &[]);
+    assert!(output.status.success(), "{}", log(&output));
+    let archived = history(&dir);
+    // compressed files are moved unchanged, the others are compressed on the way:
+    assert!(archived.contains(&"input.xml.gz".to_string()), "{archived:?}");
+    let default = archived.iter().find(|name| name.starts_with("DEFAULT_input_")).unwrap();
+    assert!(default.ends_with(".xml.gz"), "{archived:?}");
+    let residue = archived.iter().find(|name| name.starts_with("RESIDUE_input_")).unwrap();
+    assert!(residue.ends_with(".xml.zst"), "{archived:?}");
+
+    let default = read_archived(&dir, default);
+    assert!(default.starts_with("<?xml") && default.contains("<invoice_number>INV-1</invoice_number>"), "{default}");
+    assert_eq!(read_archived(&dir, "input.xml.gz"), INPUT);
+    assert!(read_archived(&dir, residue).starts_with("<?xml"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn declared_input_encoding_and_output_encodings() {
+    let dir = workspace("encodings");
+    configure(&dir, &[
+        ("[outputs]\n", "[outputs]\n[outputs.LIB001]\nencoding = \"windows-1252\"\n[outputs.LIB023]\nencoding = \"ISO-8859-2\"\n"),
+        ("nopol = \"Invoice Item without POL\"", "nopol = \"Posten für 5 €\""),
+    ]);
+    let input = "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>
+<payments><invoices>
+<invoice><invoice_owner>Happy Owner</invoice_owner><vendor>Bäckerei Brötchen</vendor><invoice_lines><line><code>1</code></line></invoice_lines></invoice>
+<invoice><owner_entity><orgId>3210613802456789</orgId></owner_entity><vendor>Café Crème</vendor><invoice_lines><line><code>2</code></line></invoice_lines></invoice>
+<invoice><vendor>Señor Niño</vendor><invoice_lines><line><code>3</code></line></invoice_lines></invoice>
+</invoices></payments>
+";
+    let latin1: Vec<u8> = input.chars().map(|c| u8::try_from(u32::from(c)).unwrap()).collect();
+    fs::write(dir.join("original_xml/input.xml"), latin1).unwrap();
+    let output = xtract(&dir, &[]);
+    assert!(output.status.success(), "{}", log(&output));
+    let archived = history(&dir);
+    let read = |prefix: &str| {
+        let name = archived.iter().find(|name| name.starts_with(prefix)).unwrap();
+        let folder = fs::read_dir(dir.join("history")).unwrap().next().unwrap().unwrap().path();
+        fs::read(folder.join(name)).unwrap()
+    };
+    let contains = |data: &[u8], part: &[u8]| data.windows(part.len()).any(|window| window == part);
+    // windows-1252 has all characters, including the euro sign:
+    let lib001 = read("LIB001_input_");
+    assert!(lib001.starts_with(b"<?xml version=\"1.0\" encoding=\"windows-1252\"?>"));
+    assert!(contains(&lib001, b"B\xe4ckerei Br\xf6tchen") && contains(&lib001, b"f\xfcr 5 \x80"));
+    // characters missing in ISO-8859-2 are written as character references:
+    let lib023 = read("LIB023_input_");
+    assert!(lib023.starts_with(b"<?xml version=\"1.0\" encoding=\"ISO-8859-2\"?>"));
+    assert!(contains(&lib023, b"Caf\xe9 Cr&#232;me") && contains(&lib023, b"f\xfcr 5 &#8364;"));
+    let default = String::from_utf8(read("DEFAULT_input_")).unwrap();
+    assert!(default.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"), "{default}");
+    assert!(default.contains("Señor Niño") && default.contains("für 5 €"), "{default}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_configuration_exits_with_config() {
+    let dir = workspace("exit-config");
+    configure(&dir, &[("element = \"payments/invoices/invoice\"", "element = ")]);
+    let output = xtract(&dir, &[]);
+    assert_eq!(output.status.code(), Some(EXIT_CONFIG), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_or_ambiguous_original_exits_with_original() {
+    let dir = workspace("exit-original");
+    let output = xtract(&dir, &[]);
+    assert_eq!(output.status.code(), Some(EXIT_ORIGINAL), "{}", log(&output));
+    original(&dir, "a.xml", None, 1);
+    original(&dir, "b.xml", None, 2);
+    let output = xtract(&dir, &[]);
+    assert_eq!(output.status.code(), Some(EXIT_ORIGINAL), "{}", log(&output));
+    assert!(dir.join("original_xml/a.xml").is_file() && dir.join("original_xml/b.xml").is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_output_dir_exits_with_io() {
+    let dir = workspace("exit-io");
+    fs::remove_dir_all(dir.join("revised_files")).unwrap();
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert_eq!(output.status.code(), Some(EXIT_IO), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn malformed_xml_exits_with_xml() {
+    let dir = workspace("exit-xml");
+    fs::write(dir.join("input.xml"), "<payments></invoices>").unwrap();
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert_eq!(output.status.code(), Some(EXIT_XML), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failing_expression_exits_with_evaluation() {
+    let dir = workspace("exit-evaluation");
+    fs::write(dir.join("input.xml"), "<?xml version=\"1.0\"?>
+<payments><invoices><invoice><vat_info><vat_percentage>high</vat_percentage></vat_info></invoice></invoices></payments>
+").unwrap();
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert_eq!(output.status.code(), Some(EXIT_EVALUATION), "{}", log(&output));
+    assert!(log(&output).contains("#1"), "{}", log(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// An XML schema for the output of `INPUT`, requiring invoice numbers of the given type.
+fn schema(number_type: &str) -> String {
+    format!(r#"<?xml version="1.0"?>
+<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+<xs:element name="payments"><xs:complexType><xs:sequence>
+<xs:element name="invoices"><xs:complexType><xs:sequence>
+<xs:element name="invoice" maxOccurs="unbounded"><xs:complexType><xs:sequence>
+<xs:element name="invoice_number" type="{number_type}"/>
+<xs:any processContents="skip" minOccurs="0" maxOccurs="unbounded"/>
+</xs:sequence><xs:anyAttribute processContents="skip"/></xs:complexType></xs:element>
+</xs:sequence></xs:complexType></xs:element>
+</xs:sequence></xs:complexType></xs:element>
+</xs:schema>
+"#)
+}
+
+#[test]
+fn outputs_not_matching_their_schema_are_kept_back() {
+    let dir = workspace("schema");
+    configure(&dir, &[("[outputs]\n", "[outputs]\n[outputs.DEFAULT]\nschema = \"schema.xsd\"\n")]);
+    fs::write(dir.join("schema.xsd"), schema("xs:string")).unwrap();
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert!(output.status.success(), "{}", log(&output));
+    assert!(history(&dir).iter().any(|name| name.starts_with("DEFAULT_input_")), "{:?}", history(&dir));
+
+    fs::write(dir.join("input.xml"), INPUT).unwrap();
+    fs::write(dir.join("schema.xsd"), schema("xs:integer")).unwrap();
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert_eq!(output.status.code(), Some(EXIT_SCHEMA), "{}", log(&output));
+    assert!(log(&output).contains("invoice_number"), "{}", log(&output));
+    // the file stays in the output directory instead of being archived:
+    let kept: Vec<_> = fs::read_dir(dir.join("revised_files")).unwrap().collect();
+    assert_eq!(kept.len(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn input_file_is_not_archived() {
+    let dir = workspace("input");
+    let output = xtract(&dir, &["--input", "input.xml"]);
+    assert!(output.status.success(), "{}", log(&output));
+    assert!(dir.join("input.xml").is_file());
+    let archived = history(&dir);
+    assert!(archived.iter().any(|name| name.starts_with("DEFAULT_input_")), "{archived:?}");
+    assert!(!archived.contains(&"input.xml".to_string()), "{archived:?}");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn original_file_is_archived() {
+    let dir = workspace("original");
+    fs::rename(dir.join("input.xml"), dir.join("original_xml/input.xml")).unwrap();
+    let output = xtract(&dir, &[]);
+    assert!(output.status.success(), "{}", log(&output));
+    assert!(!dir.join("original_xml/input.xml").exists());
+    assert!(history(&dir).contains(&"input.xml".to_string()));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use xtract::golden::*;
+
+const CASES: &str = "tests/cases";
+
+#[test]
+fn golden_cases_pass() {
+    let results = run_cases(Path::new(CASES), &HashMap::new(), &"en".to_string(), false).unwrap();
+    assert!(!results.is_empty());
+    for result in results {
+        assert!(result.passed(), "{}:\n{}", result.name, result.failures.join("\n"));
+    }
+}
+
+#[test]
+fn example_case_uses_example_config() {
+    let example = fs::read_to_string("config/config.toml").unwrap();
+    let case = fs::read_to_string(Path::new(CASES).join("example").join(CONFIG)).unwrap();
+    assert_eq!(example, case, "tests/cases/example/config.toml is out of sync with config/config.toml");
+}
+
+#[test]
+fn differing_output_fails() {
+    let dir = std::env::temp_dir().join(format!("xtract_golden_{}", std::process::id()));
+    let expected = dir.join(EXPECTED);
+    fs::create_dir_all(&expected).unwrap();
+    let case = Path::new(CASES).join("example");
+    for file in [INPUT, CONFIG] {
+        fs::copy(case.join(file), dir.join(file)).unwrap();
+    }
+    for group in ["LIB001", "LIB023"] {
+        let file = format!("{group}.xml");
+        fs::copy(case.join(EXPECTED).join(&file), expected.join(&file)).unwrap();
+    }
+    let lib023 = expected.join("LIB023.xml");
+    let changed = fs::read_to_string(&lib023).unwrap().replace("MATCH-PATTERN-1", "MATCH-PATTERN-2");
+    fs::write(&lib023, changed).unwrap();
+
+    let result = run_case(&dir, &HashMap::new(), &"en".to_string(), false);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(result.failures.len(), 2, "{:?}", result.failures);
+    assert!(result.failures[0].starts_with("LIB023: output_differs"));
+    assert!(result.failures[1].starts_with("RESIDUE: unexpected_output"));
+}
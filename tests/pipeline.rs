@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use quick_xml::events::Event;
+use chrono::NaiveDate;
+use quick_xml::Writer;
+use xtract::common::get_config;
+use xtract::{Config, Pipeline, Sink};
+
+/// Invoices for each group of the example configuration: two owned ones, a blocked one and one without owner.
+const INPUT: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<payments><invoices>
+<invoice><invoice_number>INV-1</invoice_number><owner_entity><orgId>3210705901456789</orgId></owner_entity><vat_info><vat_percentage>8.0</vat_percentage></vat_info></invoice>
+<invoice><invoice_number>INV-2</invoice_number><owner_entity><orgId>3210613802456789</orgId></owner_entity><vat_info><vat_percentage>2.5</vat_percentage></vat_info></invoice>
+<invoice><invoice_number>INV-3</invoice_number><payment_method>BLOCKEDMETHOD</payment_method></invoice>
+<invoice><invoice_number>INV-4</invoice_number></invoice>
+</invoices></payments>
+";
+
+/// The example configuration of the repository.
+fn config() -> Config {
+    let path = format!("{}/config/config.toml", env!("CARGO_MANIFEST_DIR"));
+    toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// The configuration and the input of the given golden case.
+fn case(name: &str) -> (Config, Vec<u8>) {
+    let dir = format!("{}/tests/cases/{}", env!("CARGO_MANIFEST_DIR"), name);
+    (get_config(&format!("{dir}/config.toml")).unwrap(), std::fs::read(format!("{dir}/input.xml")).unwrap())
+}
+
+/// A sink for each group of the pipeline, writing to memory.
+fn sinks(pipeline: &Pipeline) -> HashMap<String, Writer<Vec<u8>>> {
+    pipeline.groups().into_iter().map(|group| (group, Writer::new(Vec::new()))).collect()
+}
+
+fn written(sinks: &HashMap<String, Writer<Vec<u8>>>, group: &str) -> String {
+    String::from_utf8(sinks[group].get_ref().clone()).unwrap()
+}
+
+/// Records how the pipeline calls a sink.
+#[derive(Default)]
+struct Recorder {
+    calls: Vec<String>,
+    failing: bool,
+}
+
+impl Sink for Recorder {
+    fn write_frame(&mut self, events: &[Event]) -> Result<()> {
+        self.calls.push(format!("frame {}", events.len()));
+        Ok(())
+    }
+
+    fn write_element(&mut self, events: &[Event]) -> Result<()> {
+        if self.failing {
+            return Err(Error::new(ErrorKind::StorageFull, "full"));
+        }
+        let name = match &events[0] {
+            Event::Start(e) => String::from_utf8_lossy(e.name().as_ref()).to_string(),
+            _ => String::new(),
+        };
+        self.calls.push(format!("element {name}"));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.calls.push("finish".to_string());
+        Ok(())
+    }
+}
+
+#[test]
+fn groups_are_written_to_their_sinks() {
+    let config = config();
+    let msg_config = HashMap::new();
+    let pipeline = Pipeline::new(&config, &msg_config).unwrap();
+    assert_eq!(pipeline.groups(), ["LIB001", "LIB023", "RESIDUE", "DEFAULT"]);
+    let mut sinks = sinks(&pipeline);
+    let report = pipeline.run(INPUT.as_bytes(), &mut sinks).unwrap();
+    for group in pipeline.groups() {
+        let output = written(&sinks, &group);
+        assert!(output.starts_with("<?xml"), "{group}: {output}");
+        assert!(output.contains("<payments>") && output.contains("</payments>"), "{group}: {output}");
+        assert_eq!(report.elements.get(&group).copied().unwrap_or(0), 1, "{group}: {output}");
+    }
+    assert!(written(&sinks, "LIB001").contains("INV-1"));
+    assert!(written(&sinks, "LIB023").contains("INV-2"));
+    assert!(written(&sinks, "RESIDUE").contains("INV-3"));
+    assert!(written(&sinks, "DEFAULT").contains("INV-4"));
+    assert_eq!(report.residue.len(), 1);
+    assert_eq!(report.residue[0].0, 3);
+}
+
+#[test]
+fn groups_without_sink_are_dropped() {
+    let config = config();
+    let msg_config = HashMap::new();
+    let pipeline = Pipeline::new(&config, &msg_config).unwrap();
+    let mut sinks: HashMap<String, Recorder> = HashMap::new();
+    sinks.insert("DEFAULT".to_string(), Recorder::default());
+    pipeline.run(INPUT.as_bytes(), &mut sinks).unwrap();
+    assert_eq!(sinks.len(), 1);
+    // the pipeline writes the frame and the split elements, finishing is left to the caller:
+    let calls = &sinks["DEFAULT"].calls;
+    assert!(calls[0].starts_with("frame"), "{calls:?}");
+    assert!(calls.last().unwrap().starts_with("frame"), "{calls:?}");
+    assert_eq!(calls.iter().filter(|call| *call == "element invoice").count(), 1, "{calls:?}");
+    assert!(!calls.contains(&"finish".to_string()), "{calls:?}");
+}
+
+#[test]
+fn failing_sinks_end_the_run() {
+    let config = config();
+    let msg_config = HashMap::new();
+    let pipeline = Pipeline::new(&config, &msg_config).unwrap();
+    let mut sinks: HashMap<String, Recorder> = HashMap::new();
+    sinks.insert("DEFAULT".to_string(), Recorder { failing: true, ..Default::default() });
+    let err = pipeline.run(INPUT.as_bytes(), &mut sinks).unwrap_err();
+    assert_eq!(err.exit_code(), xtract::error::EXIT_IO, "{err}");
+}
+
+#[test]
+fn transformations_are_counted() {
+    let config = config();
+    let msg_config = HashMap::new();
+    let pipeline = Pipeline::new(&config, &msg_config).unwrap();
+    let mut sinks = sinks(&pipeline);
+    let report = pipeline.run(INPUT.as_bytes(), &mut sinks).unwrap();
+    assert_eq!(report.transformations.len(), config.transformations.len());
+    // the VAT rates of both owned invoices are rounded by the first rule:
+    assert_eq!(report.transformations[0].1, 2);
+    assert!(written(&sinks, "LIB001").contains("<vat_percentage>8.1</vat_percentage>"));
+    assert!(written(&sinks, "LIB023").contains("<vat_percentage>2.6</vat_percentage>"));
+}
+
+#[test]
+fn relative_dates_are_anchored_to_the_given_time() {
+    let (config, input) = case("dates");
+    let msg_config = HashMap::new();
+    let residue = |year: i32| {
+        let now = NaiveDate::from_ymd_opt(year, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let pipeline = Pipeline::new(&config, &msg_config).unwrap().at(now);
+        let mut sinks = sinks(&pipeline);
+        let report = pipeline.run(&input[..], &mut sinks).unwrap();
+        report.residue.iter().map(|(index, _)| *index).collect::<Vec<usize>>()
+    };
+    // the invoice of 1900 is less than 100 years old in 1990, but not in 2026:
+    assert!(!residue(1990).contains(&4));
+    assert!(residue(2026).contains(&4));
+}
+
+#[test]
+fn conflicting_labels_are_reported() {
+    let (config, input) = case("fanout");
+    let msg_config = HashMap::new();
+    let pipeline = Pipeline::new(&config, &msg_config).unwrap();
+    let mut sinks = sinks(&pipeline);
+    let report = pipeline.run(&input[..], &mut sinks).unwrap();
+    // INV-3 matches LIB001 and LIB023 by its owners, but only the first one counts:
+    let conflict = report.conflicts.iter().find(|(index, _)| *index == 3).unwrap();
+    assert_eq!(conflict.1, ["LIB001", "LIB023"]);
+    assert!(written(&sinks, "LIB001").contains("INV-3"));
+    assert!(!written(&sinks, "LIB023").contains("INV-3"));
+    // co-owned invoices go to every group of their owners:
+    assert!(written(&sinks, "LIB001").contains("INV-1") && written(&sinks, "LIB023").contains("INV-1"));
+}
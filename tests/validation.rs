@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fs;
+
+use xtract::validation::*;
+
+const MINIMAL: &str = r#"element = "payments/invoices/invoice"
+
+[filter]
+residue = "RESIDUE"
+
+[split]
+default = "DEFAULT"
+
+[[transformations]]
+target = "payments/invoices/invoice/vat_info/vat_percentage"
+value = "p + 1"
+[transformations.source.datafields]
+p = "payments/header/vat"
+[transformations.preconditions]
+existng = ["payments/invoices/invoice/vat_info"]
+
+[settings]
+lang = "en"
+history_size = 10
+[settings.dirs]
+original = "original_xml"
+transformed = "revised_files"
+history = "history"
+[settings.timeformats]
+history_folder = "%Y-%m-%d"
+files = "%Y-%m-%dT%H-%M-%S"
+[settings.email.mailer]
+smtp = "localhost"
+port = 25
+auth = false
+[settings.email.message]
+from = "xtract@example.com"
+reply_to = []
+to = []
+subject = "XtracT"
+"#;
+
+#[test]
+fn example_config_parses() {
+    let source = fs::read_to_string("config/config.toml").unwrap();
+    assert!(parse_config(&source).is_ok());
+}
+
+#[test]
+fn unknown_keys_are_located() {
+    let problems = parse_config(MINIMAL).unwrap_err();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert_eq!((problems[0].line, problems[0].column), (15, 1));
+    assert!(problems[0].text.starts_with("unknown key `existng` in transformations #1.preconditions"));
+}
+
+#[test]
+fn paths_outside_the_split_element_are_located() {
+    let source = MINIMAL.replace("existng", "existing");
+    let config = parse_config(&source).unwrap();
+    let problems = check_config(&source, &config, &HashMap::new(), &"en".to_string());
+    let outside: Vec<&Problem> = problems.iter().filter(|problem| problem.text.contains("outside_element")).collect();
+    assert_eq!(outside.len(), 1, "{:?}", problems);
+    assert_eq!((outside[0].line, outside[0].column), (13, 5));
+    assert!(outside[0].text.ends_with("p = payments/header/vat"));
+}
+
+#[test]
+fn invalid_patterns_are_rejected_at_load_time() {
+    let source = MINIMAL
+    .replace("existng", "existing")
+    .replace("[filter]\n", "[filter]\nallowlist.regex.\"payments/invoices/invoice/vendor_code\" = [\"^VEND-\", \"(\"]\n")
+    .replace("value = \"p + 1\"", "value = \"p + (1\"");
+    let problems = parse_config(&source).unwrap_err();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert_eq!((problems[0].line, problems[0].column), (4, 70));
+    assert_eq!(problems[0].text, "invalid regular expression \"(\": unclosed group");
+    assert!(problems[1].text.starts_with("invalid expression \"p + (1\""));
+}
+
+#[test]
+fn invalid_filter_rules_are_located() {
+    let source = MINIMAL
+    .replace("existng", "existing")
+    .replace("[split]\n", "[filter.rules]\nopen = 'payments/invoices/invoice/@status = \"open\" and'\n\n[split]\n");
+    let problems = parse_config(&source).unwrap_err();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert_eq!((problems[0].line, problems[0].column), (7, 8));
+    assert!(problems[0].text.ends_with("expected a path at the end"), "{}", problems[0].text);
+}
+
+#[test]
+fn invalid_dates_are_rejected_at_load_time() {
+    let source = MINIMAL
+    .replace("existng", "existing")
+    .replace("[split]\n", "[filter.rules]\ndue = 'payments/invoices/invoice/due_date < date(\"31.12.2026\")'\n\n[split]\n");
+    let problems = parse_config(&source).unwrap_err();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].text.ends_with("date \"31.12.2026\" at column 43 doesn't match the date format \"%Y-%m-%d\""), "{}", problems[0].text);
+
+    let source = source.replace("residue = \"RESIDUE\"\n", "residue = \"RESIDUE\"\ndate_format = \"%d.%m.%Y\"\n");
+    assert!(parse_config(&source).is_ok());
+    let source = source.replace("%d.%m.%Y", "%d.%m.%Q");
+    let problems = parse_config(&source).unwrap_err();
+    assert_eq!(problems[0].text, "invalid date format \"%d.%m.%Q\"");
+}
+
+#[test]
+fn matches_without_grouping_are_located() {
+    let source = MINIMAL
+    .replace("existng", "existing")
+    .replace("default = \"DEFAULT\"\n", "default = \"DEFAULT\"\n[split.matches]\n\"payments/invoices/invoice/owner\" = \"all\"\n");
+    let config = parse_config(&source).unwrap();
+    let problems = check_config(&source, &config, &HashMap::new(), &"en".to_string());
+    let orphans: Vec<&Problem> = problems.iter().filter(|problem| problem.text.contains("matches_without_grouping")).collect();
+    assert_eq!(orphans.len(), 1, "{:?}", problems);
+    assert_eq!((orphans[0].line, orphans[0].column), (9, 37));
+    assert!(orphans[0].text.ends_with("- payments/invoices/invoice/owner"));
+
+    let source = source.replace("= \"all\"", "= \"every\"");
+    let problems = parse_config(&source).unwrap_err();
+    assert!(problems[0].text.starts_with("unknown variant `every`"), "{}", problems[0].text);
+}
+
+#[test]
+fn invalid_csv_delimiters_are_located() {
+    let outputs = "[outputs.LIB001]\nformat = \"csv\"\ndelimiter = \"§\"\n\n[[transformations]]";
+    let source = MINIMAL.replace("existng", "existing").replacen("[[transformations]]", outputs, 1);
+    let config = parse_config(&source).unwrap();
+    let problems = check_config(&source, &config, &HashMap::new(), &"en".to_string());
+    let delimiters: Vec<&Problem> = problems.iter().filter(|problem| problem.text.contains("invalid_csv_delimiter")).collect();
+    assert_eq!(delimiters.len(), 1, "{:?}", problems);
+    assert_eq!((delimiters[0].line, delimiters[0].column), (11, 13));
+    assert!(delimiters[0].text.ends_with("LIB001 - unsupported CSV delimiter '§'"), "{}", delimiters[0].text);
+
+    let source = source.replace("\"§\"", "\";;\"");
+    let problems = parse_config(&source).unwrap_err();
+    assert_eq!(problems[0].line, 11, "{:?}", problems);
+}
+
+#[test]
+fn problems_in_every_table_are_reported() {
+    let source = MINIMAL
+    .replace("existng", "existing")
+    .replace("[filter]\n", "[filter]\nallowlist.regex.\"payments/invoices/invoice/vendor_code\" = [\"(\"]\n")
+    .replace("default = \"DEFAULT\"", "default = 1")
+    .replace("[transformations.preconditions]", "[transformations.parameters]\ndecimal_places = \"two\"\n[transformations.preconditions]")
+    .replace("history_size = 10", "history_size = \"ten\"");
+    // values of the wrong type in two tables, an invalid pattern and invalid decimal places:
+    let problems = parse_config(&source).unwrap_err();
+    let located: Vec<(usize, usize, &str)> = problems.iter()
+    .map(|problem| (problem.line, problem.column, problem.text.as_str()))
+    .collect();
+    assert_eq!(located, [
+        (4, 60, "invalid regular expression \"(\": unclosed group"),
+        (8, 11, "invalid type: integer `1`, expected a string"),
+        (16, 18, "invalid decimal_places \"two\": invalid digit found in string"),
+        (22, 16, "invalid type: string \"ten\", expected usize"),
+    ]);
+}